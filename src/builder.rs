@@ -1,14 +1,36 @@
 use crate::prism_autoloader;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
-use super::knots_objects::KnotsObject;
-use super::utils::{get_alpha_numeral, get_roman_numeral};
+use super::diagnostics::Construct;
+use super::images::ImageOptimisation;
+use super::knots_objects::{KnotsObject, Node, Span};
+use super::transpiler::{AdmonitionKind, Numbering};
 
 #[derive(Clone)]
 pub struct Title {
     pub level: u8,
     pub name: String,
     pub anchor: String,
+    /// the number of tasks done in this section
+    pub tasks_done: usize,
+    /// the number of tasks in this section
+    pub tasks_total: usize,
+}
+
+/// Counts a reference to a footnote, in a list of footnote ids with their number of references.
+/// Returns the number of the footnote, and the number of this reference to it.
+pub fn count_footnote_reference(footnotes: &mut Vec<(String, usize)>, id: &str) -> (usize, usize) {
+    match footnotes.iter().position(|(footnote, _)| footnote == id) {
+        Some(i) => {
+            footnotes[i].1 += 1;
+            (i + 1, footnotes[i].1)
+        }
+        None => {
+            footnotes.push((id.to_owned(), 1));
+            (footnotes.len(), 1)
+        }
+    }
 }
 
 /// A Builder used to generate HTML tags from Knots objects.
@@ -20,6 +42,8 @@ pub struct Builder {
     indentation: usize,
     /// the tags waiting to be closed
     tags_queue: Vec<String>,
+    /// is the line left open by `write_glued`, so the next text sticks to it ?
+    glued: bool,
     /// should we include prism ?
     pub should_include_prism: bool,
     /// should we include katex ?
@@ -28,24 +52,55 @@ pub struct Builder {
     pub should_include_mermaid: bool,
     /// keep track of the current container class
     pub current_container: String,
-    /// the number of lv1 titles
-    lv1_titles: usize,
-    /// the number of lv2 titles since the last lv1 title
-    lv2_titles: usize,
+    /// how deep we are in blocks containing other blocks, like boxes
+    nesting: usize,
+    /// the number of titles of each level since the last title of a parent level
+    title_counts: [usize; 6],
+    /// how the titles of each level are numbered
+    pub numbering: [Numbering; 6],
     /// an array to keep track of the summary
     titles: Vec<Title>,
+    /// the anchors already taken, including the explicit ids of all the titles
+    pub anchors: HashSet<String>,
+    /// what can be referenced with `@ref(id)`, by id, with its anchor and the text of the references
+    reference_targets: HashMap<String, (String, String)>,
+    /// the references written so far, with where they go in the buffer
+    references: Vec<(usize, String)>,
+    /// the number of numbered tables
+    tables: usize,
+    /// the number of numbered figures
+    figures: usize,
+    /// the number of numbered equations
+    equations: usize,
     /// the number of maths blocks
     pub maths_blocks: usize,
     /// we need to populate katex blocks after the script inclusion
     katex_buf: String,
     /// the different programming languages used in the document
     pub languages: HashSet<String>,
+    /// where the relative paths of the document start from
+    pub asset_root: PathBuf,
+    /// how the local images are optimised, if they are
+    pub image_optimisation: Option<ImageOptimisation>,
+    /// should we download the remote images to embed them ?
+    pub embed_remote: bool,
+    /// where the optimised and downloaded images are kept between renders
+    pub cache_dir: Option<PathBuf>,
+    /// the admonition kinds registered in the options
+    pub admonition_kinds: Vec<AdmonitionKind>,
+    /// the ids of the footnotes in order of first reference, with their number of references
+    footnotes: Vec<(String, usize)>,
+    /// the problems which didn't prevent the document from being written, like missing images
+    warnings: Vec<(Span, Construct, String)>,
 }
 
 impl Builder {
     #[inline]
     pub fn new() -> Self {
-        Self::default()
+        Builder {
+            numbering: Numbering::DEFAULT,
+            ..Self::default()
+        }
     }
 
     /// Prints the resulting buffer to stdout
@@ -54,16 +109,87 @@ impl Builder {
             panic!("Unclosed tags !!");
         }
 
-        self.buf
+        self.resolve_references()
+    }
+
+    /// Writes a reference to something with an id.
+    /// Since it can be defined later in the document,
+    /// the reference is only resolved once everything is written.
+    pub fn write_reference(&mut self, id: &str) {
+        self.write_content("");
+
+        // the reference goes before the line break
+        self.references.push((self.buf.len() - 1, id.to_owned()));
+    }
+
+    /// Registers something which can be referenced with `@ref(id)`
+    pub fn add_reference_target(&mut self, id: &str, anchor: &str, text: &str) {
+        self.reference_targets
+            .insert(id.to_owned(), (anchor.to_owned(), text.to_owned()));
+    }
+
+    /// Inserts the links of the references where they were written
+    fn resolve_references(self) -> String {
+        let mut resolved = String::with_capacity(self.buf.len());
+        let mut written = 0;
+
+        for (position, id) in &self.references {
+            resolved += &self.buf[written..*position];
+            written = *position;
+
+            match self.reference_targets.get(id) {
+                Some((anchor, text)) => {
+                    resolved += &format!("<a href=\"#{}\" class=\"ref\">{}</a>", anchor, text)
+                }
+                None => resolved += "<span class=\"ref missing-ref\">??</span>",
+            }
+        }
+
+        resolved += &self.buf[written..];
+        resolved
+    }
+
+    /// Counts a numbered table, returning its number and its anchor
+    pub fn add_table(&mut self, id: Option<&str>) -> (usize, String) {
+        self.tables += 1;
+        let anchor = self.add_numbered_target("Table", self.tables, id);
+        (self.tables, anchor)
+    }
+
+    /// Counts a numbered figure, returning its number and its anchor
+    pub fn add_figure(&mut self, id: Option<&str>) -> (usize, String) {
+        self.figures += 1;
+        let anchor = self.add_numbered_target("Figure", self.figures, id);
+        (self.figures, anchor)
+    }
+
+    /// Returns the anchor of a numbered table or figure, registering it if it has an id
+    fn add_numbered_target(&mut self, label: &str, number: usize, id: Option<&str>) -> String {
+        match id {
+            Some(id) => {
+                self.add_reference_target(id, id, &format!("{} {}", label, number));
+                id.to_owned()
+            }
+            None => format!("{}-{}", label.to_lowercase(), number),
+        }
+    }
+
+    /// Counts a labelled equation, returning its number
+    pub fn add_equation(&mut self, id: &str) -> usize {
+        self.equations += 1;
+        self.add_reference_target(id, id, &format!("({})", self.equations));
+        self.equations
     }
 
     /// Starts an orphan tag
     pub fn orphan_tag(&mut self, tag_name: &str, attributes: &[(&str, &str)]) {
+        self.unglue();
         self.buf += &self.format_start_tag(tag_name, attributes);
     }
 
     /// Writes to an inline tag
     pub fn inline_tag(&mut self, tag_name: &str, attributes: &[(&str, &str)], contents: &str) {
+        self.unglue();
         self.buf += &format!(
             "{blanks}<{tag}{attributes}>{contents}</{tag}>\n",
             blanks = self.blanks(),
@@ -75,6 +201,7 @@ impl Builder {
 
     /// Starts a new tag
     pub fn start_tag(&mut self, tag_name: &str, attributes: &[(&str, &str)]) {
+        self.unglue();
         self.tags_queue.push(tag_name.to_owned());
         self.buf += &self.format_start_tag(tag_name, attributes);
         self.indentation += 1;
@@ -90,11 +217,35 @@ impl Builder {
         formatted_content = formatted_content.replace("\r\n", "\n");
         formatted_content = formatted_content.replace("\n", &newline_blanks);
 
-        self.buf += &self.blanks();
+        // glued text continues the line
+        if !self.glued {
+            self.buf += &self.blanks();
+        }
+        self.glued = false;
+
         self.buf += &formatted_content;
         self.buf.push('\n');
     }
 
+    /// Writes some inline HTML without any whitespace around it,
+    /// so it sticks to the text around, like the subscript in H~2~O
+    pub fn write_glued(&mut self, html: &str) {
+        if self.buf.ends_with('\n') {
+            self.buf.pop();
+        }
+
+        self.buf += html;
+        self.glued = true;
+    }
+
+    /// Ends the line left open by `write_glued` before writing a tag
+    fn unglue(&mut self) {
+        if self.glued {
+            self.buf.push('\n');
+            self.glued = false;
+        }
+    }
+
     /// Links a div with its katex content
     pub fn write_katex_content(&mut self, content: &str, el_id: &str) {
         self.katex_buf += &format!(
@@ -116,20 +267,35 @@ impl Builder {
 
     /// Writes a Knots object
     #[inline]
-    pub fn write_knots_object(&mut self, object: Box<dyn KnotsObject>) {
+    pub fn write_knots_object(&mut self, object: &dyn KnotsObject) {
         object.write_html(self)
     }
 
     /// Writes multiple Knots objects
     #[inline]
-    pub fn write_knots_objects(&mut self, objects: &[Box<dyn KnotsObject>]) {
+    pub fn write_knots_objects(&mut self, objects: &[Node]) {
         for object in objects {
             object.write_html(self);
         }
     }
 
+    /// Writes the Knots objects contained in another block
+    pub fn write_nested_knots_objects(&mut self, objects: &[Node]) {
+        self.nesting += 1;
+        self.write_knots_objects(objects);
+        self.nesting -= 1;
+    }
+
+    /// Are we writing objects contained in another block ?
+    /// In this case, we can't switch to another container.
+    #[inline]
+    pub fn is_nested(&self) -> bool {
+        self.nesting != 0
+    }
+
     /// Ends a tag
     pub fn end_tag(&mut self) {
+        self.unglue();
         self.indentation -= 1;
         self.buf += &format!("{}</{}>\n", self.blanks(), self.tags_queue.pop().unwrap());
     }
@@ -140,47 +306,21 @@ impl Builder {
     }
 
     /// Adds a title to the summary
-    pub fn add_title(&mut self, level: u8, name: &str) -> Title {
-        let num;
-
-        match level {
-            1 => {
-                self.lv1_titles += 1;
-                // reset the count on lv2 titles since we're starting a new section
-                self.lv2_titles = 0;
-                num = get_roman_numeral(self.lv1_titles);
-            }
-
-            2 => {
-                self.lv2_titles += 1;
-                num = get_alpha_numeral(self.lv2_titles);
-            }
-            3 => num = String::new(),
-            _ => unreachable!(),
-        }
-
-        let mut anchor = String::new();
-
-        if level >= 1 {
-            anchor += &format!("{}-", self.lv1_titles);
-        }
-
-        if level >= 2 {
-            anchor += &format!("{}-", self.lv2_titles);
-        }
+    pub fn add_title(&mut self, level: u8, name: &str, id: Option<&str>) -> Title {
+        let level_index = level.clamp(1, 6) as usize - 1;
 
-        if level >= 3 {
-            anchor += "part-";
+        // reset the count of the child levels since we're starting a new section
+        self.title_counts[level_index] += 1;
+        for count in &mut self.title_counts[level_index + 1..] {
+            *count = 0;
         }
 
-        let escaped_name: String = name
-            .replace(" ", "-")
-            .chars()
-            .into_iter()
-            .filter(|&c| c.is_ascii_alphanumeric() || "_-!?".contains(c))
-            .collect();
+        let num = self.numbering[level_index].format(&self.title_counts[..=level_index]);
 
-        anchor.push_str(&escaped_name);
+        let anchor = match id {
+            Some(id) => id.to_owned(),
+            None => self.title_anchor(level_index, name),
+        };
 
         let name = if !num.is_empty() {
             format!("{} - {}", num, name)
@@ -188,16 +328,99 @@ impl Builder {
             name.to_owned()
         };
 
+        if let Some(id) = id {
+            self.add_reference_target(id, &anchor, &name);
+        }
+
         let title = Title {
             anchor,
             level,
             name,
+            tasks_done: 0,
+            tasks_total: 0,
         };
 
         self.titles.push(title.clone());
         title
     }
 
+    /// Builds a unique anchor for a title without an explicit id
+    fn title_anchor(&mut self, level_index: usize, name: &str) -> String {
+        // the numbers of all the parent levels are used so anchors rarely collide
+        let mut anchor = String::new();
+
+        for count in &self.title_counts[..=level_index] {
+            anchor += &format!("{}-", count);
+        }
+
+        let escaped_name: String = name
+            .replace(" ", "-")
+            .chars()
+            .filter(|&c| c.is_alphanumeric() || "_-!?".contains(c))
+            .collect();
+
+        anchor.push_str(&escaped_name);
+
+        // an explicit id may still look like this anchor
+        let mut unique_anchor = anchor.clone();
+        let mut duplicates = 1;
+
+        while self.anchors.contains(&unique_anchor) {
+            duplicates += 1;
+            unique_anchor = format!("{}-{}", anchor, duplicates);
+        }
+
+        self.anchors.insert(unique_anchor.clone());
+        unique_anchor
+    }
+
+    /// Counts a task in the current section and in its parent sections
+    pub fn add_task(&mut self, done: bool) {
+        let mut level = u8::MAX;
+
+        for title in self.titles.iter_mut().rev() {
+            if title.level < level {
+                level = title.level;
+                title.tasks_total += 1;
+
+                if done {
+                    title.tasks_done += 1;
+                }
+            }
+
+            if level == 1 {
+                break;
+            }
+        }
+    }
+
+    /// Adds a reference to a footnote.
+    /// Returns the number of the footnote, and the number of this reference to it.
+    pub fn add_footnote_reference(&mut self, id: &str) -> (usize, usize) {
+        count_footnote_reference(&mut self.footnotes, id)
+    }
+
+    /// Returns the ids of the referenced footnotes with their number of references
+    pub fn get_footnotes(&self) -> &[(String, usize)] {
+        &self.footnotes
+    }
+
+    /// Resolves a path written in the document against the asset root
+    pub fn resolve_path(&self, path: &str) -> PathBuf {
+        // joining an absolute path replaces the root
+        self.asset_root.join(Path::new(path))
+    }
+
+    /// Reports a problem which doesn't prevent the document from being written
+    pub fn add_warning(&mut self, span: Span, construct: Construct, explanation: String) {
+        self.warnings.push((span, construct, explanation));
+    }
+
+    /// Returns the problems found while writing the document
+    pub fn get_warnings(&self) -> &[(Span, Construct, String)] {
+        &self.warnings
+    }
+
     /// Returns the number of tabs corresponding to the indentation
     fn blanks(&self) -> String {
         let mut blanks = String::new();
@@ -231,3 +454,80 @@ impl Builder {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_tasks_in_parent_sections() {
+        let mut builder = Builder::new();
+        builder.add_title(1, "Chores", None);
+        builder.add_task(true);
+        builder.add_title(2, "Kitchen", None);
+        builder.add_task(false);
+        builder.add_title(2, "Garden", None);
+        builder.add_task(true);
+        builder.add_task(true);
+
+        let progress: Vec<_> = builder
+            .get_summary()
+            .iter()
+            .map(|title| (title.tasks_done, title.tasks_total))
+            .collect();
+
+        assert_eq!(progress, [(3, 4), (0, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn builds_unique_title_anchors() {
+        let mut builder = Builder::new();
+        builder.anchors.insert("2-Intro".to_owned());
+
+        let anchors: Vec<_> = [
+            (1, "Intro", None),
+            (1, "Intro", None),
+            (2, "Café: déjà vu?", None),
+            (1, "Outro", Some("the-end")),
+        ]
+        .iter()
+        .map(|&(level, name, id)| builder.add_title(level, name, id).anchor)
+        .collect();
+
+        assert_eq!(
+            anchors,
+            ["1-Intro", "2-Intro-2", "2-1-Café-déjà-vu?", "the-end"]
+        );
+    }
+
+    #[test]
+    fn resolves_paths_against_the_asset_root() {
+        let mut builder = Builder::new();
+        builder.asset_root = PathBuf::from("notes/assets");
+
+        assert_eq!(
+            builder.resolve_path("img/cat.png"),
+            Path::new("notes/assets/img/cat.png")
+        );
+        assert_eq!(
+            builder.resolve_path("/tmp/cat.png"),
+            Path::new("/tmp/cat.png")
+        );
+    }
+
+    #[test]
+    fn resolves_references_written_before_their_target() {
+        let mut builder = Builder::new();
+        builder.write_content("Icon \u{E000} and \u{E001} here, see");
+        builder.write_reference("intro");
+        builder.write_reference("nope");
+        builder.add_reference_target("intro", "1-Intro", "I - Intro");
+
+        let html = builder.into_result();
+
+        assert_eq!(
+            html,
+            "Icon \u{E000} and \u{E001} here, see\n<a href=\"#1-Intro\" class=\"ref\">I - Intro</a>\n<span class=\"ref missing-ref\">??</span>\n"
+        );
+    }
+}
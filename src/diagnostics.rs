@@ -0,0 +1,202 @@
+use std::{error::Error, fmt, io};
+
+/// The Knots constructs a diagnostic can be about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Construct {
+    Title,
+    Paragraph,
+    BlockQuote,
+    Admonition,
+    List,
+    Table,
+    CodeFence,
+    MathsBlock,
+    Image,
+    Footnote,
+    Id,
+    CrossReference,
+    Link,
+    Metadata,
+}
+
+impl fmt::Display for Construct {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Construct::Title => "title",
+            Construct::Paragraph => "paragraph",
+            Construct::BlockQuote => "block quote",
+            Construct::Admonition => "admonition",
+            Construct::List => "list",
+            Construct::Table => "table",
+            Construct::CodeFence => "code fence",
+            Construct::MathsBlock => "maths block",
+            Construct::Image => "image",
+            Construct::Footnote => "footnote",
+            Construct::Id => "id",
+            Construct::CrossReference => "cross-reference",
+            Construct::Link => "link",
+            Construct::Metadata => "metadata",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// How serious a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// the document can't be rendered
+    Error,
+    /// the document can be rendered, but maybe not the way it was meant to be
+    Warning,
+}
+
+/// A problem found at a given position of a Knots document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// the name of the document
+    pub file: String,
+    /// the line of the problem, starting at 1
+    pub line: usize,
+    /// the column of the problem, starting at 1
+    pub column: usize,
+    /// the source line where the problem is
+    pub excerpt: String,
+    /// the construct which failed
+    pub construct: Construct,
+    /// what went wrong
+    pub explanation: String,
+    /// whether it prevents the document from being rendered
+    pub severity: Severity,
+}
+
+impl Diagnostic {
+    /// Creates an error pointing at `offset` bytes in the `source` document
+    pub fn new(
+        file: &str,
+        source: &str,
+        offset: usize,
+        construct: Construct,
+        explanation: String,
+    ) -> Self {
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = source[offset..]
+            .find('\n')
+            .map(|i| offset + i)
+            .unwrap_or_else(|| source.len());
+
+        Diagnostic {
+            file: file.to_owned(),
+            line: before.matches('\n').count() + 1,
+            column: source[line_start..offset].chars().count() + 1,
+            excerpt: source[line_start..line_end]
+                .trim_end_matches('\r')
+                .to_owned(),
+            construct,
+            explanation,
+            severity: Severity::Error,
+        }
+    }
+
+    /// Creates a warning pointing at `offset` bytes in the `source` document
+    pub fn warning(
+        file: &str,
+        source: &str,
+        offset: usize,
+        construct: Construct,
+        explanation: String,
+    ) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::new(file, source, offset, construct, explanation)
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Error => writeln!(
+                f,
+                "{}:{}:{}: invalid {}: {}",
+                self.file, self.line, self.column, self.construct, self.explanation
+            )?,
+            Severity::Warning => writeln!(
+                f,
+                "{}:{}:{}: warning about the {}: {}",
+                self.file, self.line, self.column, self.construct, self.explanation
+            )?,
+        }
+
+        // point at the faulty column below the excerpt
+        let padding: String = self
+            .excerpt
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        writeln!(f, "    {}", self.excerpt)?;
+        write!(f, "    {}^", padding)
+    }
+}
+
+/// An error returned when a Knots document can't be parsed
+#[derive(Debug)]
+pub enum ParseError {
+    /// the document couldn't be read
+    Io { file: String, error: io::Error },
+    /// the document has syntax errors
+    Syntax(Vec<Diagnostic>),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Io { file, error } => write!(f, "Failed to open file {}: {}", file, error),
+            ParseError::Syntax(diagnostics) => {
+                for (i, diagnostic) in diagnostics.iter().enumerate() {
+                    if i != 0 {
+                        writeln!(f)?;
+                    }
+                    writeln!(f, "{}", diagnostic)?;
+                }
+
+                write!(f, "Parser failed with {} error(s)", diagnostics.len())
+            }
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Io { error, .. } => Some(error),
+            ParseError::Syntax(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_at_the_faulty_column() {
+        let source = "# Intro\n\tSome `çode\n";
+        let diagnostic = Diagnostic::new(
+            "notes.md",
+            source,
+            source.find('`').unwrap(),
+            Construct::Paragraph,
+            "this inline code is never closed".to_owned(),
+        );
+
+        assert_eq!((diagnostic.line, diagnostic.column), (2, 7));
+        assert_eq!(
+            diagnostic.to_string(),
+            "notes.md:2:7: invalid paragraph: this inline code is never closed\n    \tSome `çode\n    \t     ^"
+        );
+    }
+}
@@ -0,0 +1,572 @@
+use std::{
+    convert::TryInto,
+    fs,
+    io::{self, Cursor, Read},
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+
+use image::{
+    codecs::{jpeg::JpegEncoder, png::PngEncoder, webp::WebPEncoder},
+    imageops::FilterType,
+    metadata::Orientation,
+    DynamicImage, ImageDecoder, ImageReader, ImageResult,
+};
+use sha2::{Digest, Sha256};
+
+use super::utils::sniff_image_type;
+
+/// The largest remote image we download, in bytes
+const MAX_REMOTE_IMAGE_SIZE: u64 = 50 * 1024 * 1024;
+
+/// The formats of the optimised images, with their extension and MIME type
+const OUTPUT_TYPES: &[(&str, &str)] = &[
+    ("jpg", "image/jpeg"),
+    ("webp", "image/webp"),
+    ("png", "image/png"),
+];
+
+/// The format the optimised images are encoded to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// lossy, with the quality of the settings
+    Jpeg,
+    /// lossless, which suits screenshots better than photos
+    Webp,
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jpeg" | "jpg" => Ok(ImageFormat::Jpeg),
+            "webp" => Ok(ImageFormat::Webp),
+            _ => Err(format!("unknown image format {}, use jpeg or webp", s)),
+        }
+    }
+}
+
+/// How the local images are optimised before being embedded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageOptimisation {
+    /// the images wider than this, in pixels, are downsized
+    pub max_width: u32,
+    /// the quality of the JPEG images, from 1 to 100.
+    /// It's ignored when the format is WebP, which is lossless.
+    pub quality: u8,
+    /// the format of the optimised images.
+    /// When it's JPEG, the images with transparency are encoded to PNG instead.
+    pub format: ImageFormat,
+}
+
+impl Default for ImageOptimisation {
+    fn default() -> Self {
+        ImageOptimisation {
+            max_width: 1600,
+            quality: 80,
+            format: ImageFormat::Jpeg,
+        }
+    }
+}
+
+/// Optimises a PNG, JPEG or WebP image, returning its new contents and MIME type.
+/// The other images are left as they are, and so are the images which don't need
+/// to be downsized and wouldn't get lighter.
+/// The results are cached in `cache_dir` if given, by hash of the image and of the settings.
+pub(crate) fn optimise_image(
+    data: &[u8],
+    mime_type: &str,
+    settings: &ImageOptimisation,
+    cache_dir: Option<&Path>,
+) -> ImageResult<Option<(Vec<u8>, &'static str)>> {
+    if !["image/png", "image/jpeg", "image/webp"].contains(&mime_type) {
+        return Ok(None);
+    }
+
+    // the same image gives another result with other settings
+    let key = hash(&[data, format!("{:?}", settings).as_bytes()].concat());
+
+    let cache_dir = cache_dir.map(|dir| dir.join("images"));
+
+    if let Some(cache_dir) = &cache_dir {
+        for (extension, output_type) in OUTPUT_TYPES {
+            if let Ok(cached) = fs::read(cache_dir.join(format!("{}.{}", key, extension))) {
+                return Ok(Some((cached, output_type)));
+            }
+        }
+    }
+
+    let (image, orientation) = decode(data)?;
+    let (mut optimised, mut extension, mut output_type) = encode(&image, settings)?;
+
+    // re-encoding a small screenshot can make it heavier, and lossy.
+    // The original is only kept without its metadata, like the camera and the GPS position,
+    // which also holds its orientation, so a turned one is re-encoded
+    if image.width() <= settings.max_width && orientation == Orientation::NoTransforms {
+        let original = OUTPUT_TYPES
+            .iter()
+            .find(|(_, output_type)| *output_type == mime_type)
+            .and_then(|&(extension, output_type)| {
+                let stripped = strip_metadata(data, mime_type)?;
+                Some((stripped, extension, output_type))
+            });
+
+        if let Some((stripped, original_extension, original_type)) = original {
+            if optimised.len() >= stripped.len() {
+                optimised = stripped;
+                extension = original_extension;
+                output_type = original_type;
+            }
+        }
+    }
+
+    // the cache is only there to speed things up, the image is fine without it
+    if let Some(cache_dir) = &cache_dir {
+        fs::create_dir_all(cache_dir)
+            .and_then(|_| fs::write(cache_dir.join(format!("{}.{}", key, extension)), &optimised))
+            .ok();
+    }
+
+    Ok(Some((optimised, output_type)))
+}
+
+/// Decodes an image, turning it the way its metadata says.
+/// Returns the image with the orientation it was turned by.
+fn decode(data: &[u8]) -> ImageResult<(DynamicImage, Orientation)> {
+    let mut decoder = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()?
+        .into_decoder()?;
+
+    // camera photos are often stored sideways, with their orientation in their EXIF metadata
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    Ok((image, orientation))
+}
+
+/// Removes the metadata of a PNG, JPEG or WebP image: its EXIF, XMP and text,
+/// keeping what changes how it looks like its colour profile.
+/// Returns `None` if the image isn't well-formed.
+fn strip_metadata(data: &[u8], mime_type: &str) -> Option<Vec<u8>> {
+    match mime_type {
+        "image/jpeg" => strip_jpeg_metadata(data),
+        "image/png" => strip_png_metadata(data),
+        "image/webp" => strip_webp_metadata(data),
+        _ => None,
+    }
+}
+
+/// Removes the APP1 segments of a JPEG image, holding its EXIF and XMP metadata,
+/// the APP13 ones, holding its IPTC metadata, and its comments
+fn strip_jpeg_metadata(data: &[u8]) -> Option<Vec<u8>> {
+    let mut stripped = data.get(..2).filter(|soi| soi == &[0xff, 0xd8])?.to_vec();
+    let mut rest = &data[2..];
+
+    loop {
+        let marker = match rest {
+            [0xff, marker, ..] => *marker,
+            _ => return None,
+        };
+
+        // the compressed data comes after the start of scan, without any metadata
+        if marker == 0xda {
+            stripped.extend_from_slice(rest);
+            return Some(stripped);
+        }
+
+        let length = u16::from_be_bytes([*rest.get(2)?, *rest.get(3)?]) as usize;
+        let segment = rest.get(..2 + length)?;
+
+        if ![0xe1, 0xed, 0xfe].contains(&marker) {
+            stripped.extend_from_slice(segment);
+        }
+
+        rest = &rest[segment.len()..];
+    }
+}
+
+/// Removes the `eXIf` and text chunks of a PNG image
+fn strip_png_metadata(data: &[u8]) -> Option<Vec<u8>> {
+    let mut stripped = data
+        .get(..8)
+        .filter(|signature| signature == b"\x89PNG\r\n\x1a\n")?
+        .to_vec();
+    let mut rest = &data[8..];
+
+    while !rest.is_empty() {
+        let length = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+        // the length, the type, the data and the CRC
+        let chunk = rest.get(..12 + length)?;
+
+        if ![&b"eXIf"[..], b"tEXt", b"zTXt", b"iTXt"].contains(&&chunk[4..8]) {
+            stripped.extend_from_slice(chunk);
+        }
+
+        rest = &rest[chunk.len()..];
+    }
+
+    Some(stripped)
+}
+
+/// Removes the `EXIF` and `XMP ` chunks of a WebP image,
+/// along with the flags saying it has them
+fn strip_webp_metadata(data: &[u8]) -> Option<Vec<u8>> {
+    if data.get(..4)? != b"RIFF" || data.get(8..12)? != b"WEBP" {
+        return None;
+    }
+
+    let mut stripped = data[..12].to_vec();
+    let mut rest = &data[12..];
+
+    while !rest.is_empty() {
+        let length = u32::from_le_bytes(rest.get(4..8)?.try_into().ok()?) as usize;
+        let chunk = rest.get(..8 + length)?;
+        // the chunks are padded to an even length
+        let chunk = &rest[..(chunk.len() + length % 2).min(rest.len())];
+
+        match &chunk[..4] {
+            b"EXIF" | b"XMP " => (),
+            b"VP8X" => {
+                let start = stripped.len();
+                stripped.extend_from_slice(chunk);
+                // the EXIF and XMP flags
+                *stripped.get_mut(start + 8)? &= !0b1100;
+            }
+            _ => stripped.extend_from_slice(chunk),
+        }
+
+        rest = &rest[chunk.len()..];
+    }
+
+    let riff_size = (stripped.len() - 8) as u32;
+    stripped[4..8].copy_from_slice(&riff_size.to_le_bytes());
+
+    Some(stripped)
+}
+
+/// Downsizes and encodes an image, without its metadata.
+/// Returns its contents, its extension and its MIME type.
+fn encode(
+    image: &DynamicImage,
+    settings: &ImageOptimisation,
+) -> ImageResult<(Vec<u8>, &'static str, &'static str)> {
+    let resized;
+    let image = if image.width() > settings.max_width {
+        // the height is only bounded so the aspect ratio is kept
+        resized = image.resize(settings.max_width, u32::MAX, FilterType::Lanczos3);
+        &resized
+    } else {
+        image
+    };
+
+    let has_alpha = image.color().has_alpha();
+    let mut buf = Vec::new();
+
+    let (extension, output_type) = match settings.format {
+        ImageFormat::Jpeg if !has_alpha => {
+            let encoder = JpegEncoder::new_with_quality(&mut buf, settings.quality);
+            image.to_rgb8().write_with_encoder(encoder)?;
+            OUTPUT_TYPES[0]
+        }
+        ImageFormat::Jpeg => {
+            image
+                .to_rgba8()
+                .write_with_encoder(PngEncoder::new(&mut buf))?;
+            OUTPUT_TYPES[2]
+        }
+        ImageFormat::Webp if has_alpha => {
+            let encoder = WebPEncoder::new_lossless(&mut buf);
+            image.to_rgba8().write_with_encoder(encoder)?;
+            OUTPUT_TYPES[1]
+        }
+        ImageFormat::Webp => {
+            let encoder = WebPEncoder::new_lossless(&mut buf);
+            image.to_rgb8().write_with_encoder(encoder)?;
+            OUTPUT_TYPES[1]
+        }
+    };
+
+    Ok((buf, extension, output_type))
+}
+
+/// Downloads a remote image.
+/// The images are cached in `cache_dir` if given, by hash of their url,
+/// so they're only downloaded once.
+/// Fails if the response is too large or isn't an image.
+pub(crate) fn fetch_remote_image(url: &str, cache_dir: Option<&Path>) -> io::Result<Vec<u8>> {
+    let cache_file = cache_dir.map(|dir| dir.join("remote").join(hash(url.as_bytes())));
+
+    if let Some(cached) = cache_file.as_ref().and_then(|file| fs::read(file).ok()) {
+        return Ok(cached);
+    }
+
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(30))
+        .build();
+    let response = agent.get(url).call().map_err(io::Error::other)?;
+
+    let image = read_limited(response.into_reader(), MAX_REMOTE_IMAGE_SIZE)?;
+
+    if sniff_image_type(&image).is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the response isn't an image",
+        ));
+    }
+
+    // the cache is only there to speed things up, the image is fine without it
+    if let Some(cache_file) = &cache_file {
+        if let Some(dir) = cache_file.parent() {
+            fs::create_dir_all(dir)
+                .and_then(|_| fs::write(cache_file, &image))
+                .ok();
+        }
+    }
+
+    Ok(image)
+}
+
+/// Reads everything from a reader, failing if there's more than `limit` bytes
+fn read_limited(reader: impl Read, limit: u64) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+
+    // one more byte tells if there was more to read
+    reader.take(limit + 1).read_to_end(&mut data)?;
+
+    if data.len() as u64 > limit {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the response is larger than {} bytes", limit),
+        ));
+    }
+
+    Ok(data)
+}
+
+/// Hashes some data to name a cached file
+fn hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+    use std::{io::Write, net::TcpListener, thread};
+
+    /// Encodes a PNG image of this size, with some details so it doesn't compress too well
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([(x * 7) as u8, (y * 13) as u8, ((x ^ y) * 29) as u8])
+        });
+
+        let mut buf = Vec::new();
+        DynamicImage::ImageRgb8(image)
+            .write_with_encoder(PngEncoder::new(&mut buf))
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    fn keeps_small_images_which_wouldnt_get_lighter() {
+        let original = png(4, 4);
+        let (optimised, mime_type) =
+            optimise_image(&original, "image/png", &ImageOptimisation::default(), None)
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(optimised, original);
+        assert_eq!(mime_type, "image/png");
+    }
+
+    #[test]
+    fn downsizes_wide_images() {
+        let settings = ImageOptimisation {
+            max_width: 50,
+            ..ImageOptimisation::default()
+        };
+
+        let (optimised, mime_type) = optimise_image(&png(200, 100), "image/png", &settings, None)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(mime_type, "image/jpeg");
+        let (image, _) = decode(&optimised).unwrap();
+        assert_eq!((image.width(), image.height()), (50, 25));
+    }
+
+    #[test]
+    fn leaves_other_images_alone() {
+        let gif = b"GIF89a\x01\x00\x01\x00";
+        let optimised =
+            optimise_image(gif, "image/gif", &ImageOptimisation::default(), None).unwrap();
+
+        assert!(optimised.is_none());
+    }
+
+    /// Answers `requests` requests on a local port, returning the url of the server.
+    /// It serves an image at `/photo.png` and a web page at `/page.html`.
+    fn serve(requests: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 1024];
+                let length = stream.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..length]);
+
+                let (status, body) = match request.split_whitespace().nth(1) {
+                    Some("/photo.png") => ("200 OK", png(4, 4)),
+                    Some("/page.html") => ("200 OK", b"<html></html>".to_vec()),
+                    _ => ("404 Not Found", b"not found".to_vec()),
+                };
+
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+
+        url
+    }
+
+    /// A new empty cache directory for a test
+    fn cache_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("knots-cache-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    #[test]
+    fn downloads_remote_images_once() {
+        let url = format!("{}/photo.png", serve(1));
+        let cache_dir = cache_dir("download");
+
+        let image = fetch_remote_image(&url, Some(&cache_dir)).unwrap();
+        assert_eq!(image, png(4, 4));
+
+        // the server is gone, so this one comes from the cache
+        let cached = fetch_remote_image(&url, Some(&cache_dir)).unwrap();
+        assert_eq!(cached, image);
+    }
+
+    #[test]
+    fn fails_on_missing_remote_images() {
+        let url = format!("{}/missing.png", serve(1));
+        assert!(fetch_remote_image(&url, Some(&cache_dir("missing"))).is_err());
+    }
+
+    #[test]
+    fn fails_on_remote_files_which_arent_images() {
+        let url = format!("{}/page.html", serve(1));
+        let cache_dir = cache_dir("page");
+
+        assert!(fetch_remote_image(&url, Some(&cache_dir)).is_err());
+        assert!(!cache_dir.exists());
+    }
+
+    #[test]
+    fn fails_on_too_large_responses() {
+        assert_eq!(read_limited(&[1, 2, 3][..], 3).unwrap(), vec![1, 2, 3]);
+        assert!(read_limited(&[1, 2, 3, 4][..], 3).is_err());
+    }
+
+    /// Encodes a JPEG image of this size
+    fn jpeg(width: u32, height: u32, quality: u8) -> Vec<u8> {
+        let (image, _) = decode(&png(width, height)).unwrap();
+
+        let mut buf = Vec::new();
+        let encoder = JpegEncoder::new_with_quality(&mut buf, quality);
+        image.to_rgb8().write_with_encoder(encoder).unwrap();
+        buf
+    }
+
+    #[test]
+    fn strips_the_metadata_of_kept_images() {
+        let original = jpeg(4, 4, 20);
+
+        // an APP1 segment with an empty EXIF directory, and a GPS position after it
+        let mut exif = b"Exif\0\0MM\0*\0\0\0\x08\0\0\0\0\0\0GPS 48.8584 N 2.2945 E".to_vec();
+        let mut with_exif = original[..2].to_vec();
+        with_exif.extend_from_slice(&[0xff, 0xe1]);
+        with_exif.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+        with_exif.append(&mut exif);
+        with_exif.extend_from_slice(&original[2..]);
+
+        let settings = ImageOptimisation {
+            quality: 100,
+            ..ImageOptimisation::default()
+        };
+        let (optimised, mime_type) = optimise_image(&with_exif, "image/jpeg", &settings, None)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(mime_type, "image/jpeg");
+        assert_eq!(optimised, original);
+    }
+
+    #[test]
+    fn strips_the_text_chunks_of_pngs() {
+        let original = png(4, 4);
+
+        // the chunks go after the IHDR one, with a dummy CRC
+        let mut with_text = original[..33].to_vec();
+        for (kind, data) in &[(&b"tEXt"[..], &b"Author\0Me"[..]), (b"eXIf", b"MM\0*")] {
+            with_text.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            with_text.extend_from_slice(kind);
+            with_text.extend_from_slice(data);
+            with_text.extend_from_slice(&[0; 4]);
+        }
+        with_text.extend_from_slice(&original[33..]);
+
+        assert_eq!(strip_png_metadata(&with_text).unwrap(), original);
+        assert!(strip_png_metadata(&with_text[..40]).is_none());
+    }
+
+    #[test]
+    fn strips_the_metadata_chunks_of_webps() {
+        let chunk = |kind: &[u8], data: &[u8]| {
+            let mut chunk = kind.to_vec();
+            chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            chunk.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                chunk.push(0);
+            }
+            chunk
+        };
+        let riff = |chunks: &[Vec<u8>]| {
+            let body = chunks.concat();
+            let mut riff = b"RIFF".to_vec();
+            riff.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+            riff.extend_from_slice(b"WEBP");
+            riff.extend_from_slice(&body);
+            riff
+        };
+
+        let with_exif = riff(&[
+            chunk(b"VP8X", &[0b1100, 0, 0, 0, 3, 0, 0, 3, 0, 0]),
+            chunk(b"VP8L", b"pixels"),
+            chunk(b"EXIF", b"MM\0*\0"),
+            chunk(b"XMP ", b"<x/>"),
+        ]);
+        let without = riff(&[
+            chunk(b"VP8X", &[0, 0, 0, 0, 3, 0, 0, 3, 0, 0]),
+            chunk(b"VP8L", b"pixels"),
+        ]);
+
+        assert_eq!(strip_webp_metadata(&with_exif).unwrap(), without);
+    }
+}
@@ -1,17 +1,186 @@
 use base64::encode;
+use serde::Serialize;
 
 use super::builder::Builder;
-use super::utils::escape_html;
+use super::diagnostics::Construct;
+use super::images::{fetch_remote_image, optimise_image};
+use super::prism_autoloader::canonical_language;
+use super::svg::sanitize_svg;
+use super::utils::{escape_html, sniff_image_type};
 
 /// Trait representing any Knots Object.
-pub trait KnotsObject {
-    fn write_html(&self, _builder: &mut Builder) {
-        unimplemented!();
+pub(crate) trait KnotsObject {
+    fn write_html(&self, builder: &mut Builder);
+}
+
+/// The position of a node in the source document
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct Span {
+    /// the byte offset where the node starts
+    pub start: usize,
+    /// the byte offset where the node ends
+    pub end: usize,
+    /// the line where the node starts, starting at 1
+    pub line: usize,
+    /// the column where the node starts, starting at 1
+    pub column: usize,
+}
+
+/// Where a block is placed horizontally
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Alignment {
+    Left,
+    Right,
+    Center,
+}
+
+impl Alignment {
+    /// The name of the alignment, as written in the documents
+    pub fn name(self) -> &'static str {
+        match self {
+            Alignment::Left => "left",
+            Alignment::Right => "right",
+            Alignment::Center => "center",
+        }
+    }
+}
+
+/// Any node of a Knots document
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type")]
+pub enum Node {
+    Title(Title),
+    Paragraph(Paragraph),
+    BlockQuote(BlockQuote),
+    Admonition(Admonition),
+    List(List),
+    Table(Table),
+    BasicText(BasicText),
+    Italic(Italic),
+    Bold(Bold),
+    Strikethrough(Strikethrough),
+    Highlight(Highlight),
+    Subscript(Subscript),
+    Superscript(Superscript),
+    Keyboard(Keyboard),
+    Link(Link),
+    LinkDefinition(LinkDefinition),
+    FootnoteReference(FootnoteReference),
+    FootnoteDefinition(FootnoteDefinition),
+    CrossReference(CrossReference),
+    Image(Image),
+    HorizontalRule(HorizontalRule),
+    InlineCode(InlineCode),
+    InlineMaths(InlineMaths),
+    CodeBlock(CodeBlock),
+    MathsBlock(MathsBlock),
+    Mermaid(Mermaid),
+}
+
+impl Node {
+    /// Returns the position of this node in the source document
+    pub fn span(&self) -> Span {
+        match self {
+            Node::Title(title) => title.span,
+            Node::Paragraph(paragraph) => paragraph.span,
+            Node::BlockQuote(quote) => quote.span,
+            Node::Admonition(admonition) => admonition.span,
+            Node::List(list) => list.span,
+            Node::Table(table) => table.span,
+            Node::BasicText(text) => text.span,
+            Node::Italic(italic) => italic.span,
+            Node::Bold(bold) => bold.span,
+            Node::Strikethrough(strikethrough) => strikethrough.span,
+            Node::Highlight(highlight) => highlight.span,
+            Node::Subscript(subscript) => subscript.span,
+            Node::Superscript(superscript) => superscript.span,
+            Node::Keyboard(keyboard) => keyboard.span,
+            Node::Link(link) => link.span,
+            Node::LinkDefinition(definition) => definition.span,
+            Node::FootnoteReference(reference) => reference.span,
+            Node::FootnoteDefinition(definition) => definition.span,
+            Node::CrossReference(reference) => reference.span,
+            Node::Image(image) => image.span,
+            Node::HorizontalRule(hr) => hr.span,
+            Node::InlineCode(code) => code.span,
+            Node::InlineMaths(maths) => maths.span,
+            Node::CodeBlock(code) => code.span,
+            Node::MathsBlock(maths) => maths.span,
+            Node::Mermaid(mermaid) => mermaid.span,
+        }
+    }
+
+    /// Returns a mutable reference to the position of this node in the source document
+    pub fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Node::Title(title) => &mut title.span,
+            Node::Paragraph(paragraph) => &mut paragraph.span,
+            Node::BlockQuote(quote) => &mut quote.span,
+            Node::Admonition(admonition) => &mut admonition.span,
+            Node::List(list) => &mut list.span,
+            Node::Table(table) => &mut table.span,
+            Node::BasicText(text) => &mut text.span,
+            Node::Italic(italic) => &mut italic.span,
+            Node::Bold(bold) => &mut bold.span,
+            Node::Strikethrough(strikethrough) => &mut strikethrough.span,
+            Node::Highlight(highlight) => &mut highlight.span,
+            Node::Subscript(subscript) => &mut subscript.span,
+            Node::Superscript(superscript) => &mut superscript.span,
+            Node::Keyboard(keyboard) => &mut keyboard.span,
+            Node::Link(link) => &mut link.span,
+            Node::LinkDefinition(definition) => &mut definition.span,
+            Node::FootnoteReference(reference) => &mut reference.span,
+            Node::FootnoteDefinition(definition) => &mut definition.span,
+            Node::CrossReference(reference) => &mut reference.span,
+            Node::Image(image) => &mut image.span,
+            Node::HorizontalRule(hr) => &mut hr.span,
+            Node::InlineCode(code) => &mut code.span,
+            Node::InlineMaths(maths) => &mut maths.span,
+            Node::CodeBlock(code) => &mut code.span,
+            Node::MathsBlock(maths) => &mut maths.span,
+            Node::Mermaid(mermaid) => &mut mermaid.span,
+        }
     }
 }
 
+impl KnotsObject for Node {
+    fn write_html(&self, builder: &mut Builder) {
+        match self {
+            Node::Title(title) => title.write_html(builder),
+            Node::Paragraph(paragraph) => paragraph.write_html(builder),
+            Node::BlockQuote(quote) => quote.write_html(builder),
+            Node::Admonition(admonition) => admonition.write_html(builder),
+            Node::List(list) => list.write_html(builder),
+            Node::Table(table) => table.write_html(builder),
+            Node::BasicText(text) => text.write_html(builder),
+            Node::Italic(italic) => italic.write_html(builder),
+            Node::Bold(bold) => bold.write_html(builder),
+            Node::Strikethrough(strikethrough) => strikethrough.write_html(builder),
+            Node::Highlight(highlight) => highlight.write_html(builder),
+            Node::Subscript(subscript) => subscript.write_html(builder),
+            Node::Superscript(superscript) => superscript.write_html(builder),
+            Node::Keyboard(keyboard) => keyboard.write_html(builder),
+            Node::Link(link) => link.write_html(builder),
+            Node::LinkDefinition(definition) => definition.write_html(builder),
+            Node::FootnoteReference(reference) => reference.write_html(builder),
+            Node::FootnoteDefinition(definition) => definition.write_html(builder),
+            Node::CrossReference(reference) => reference.write_html(builder),
+            Node::Image(image) => image.write_html(builder),
+            Node::HorizontalRule(hr) => hr.write_html(builder),
+            Node::InlineCode(code) => code.write_html(builder),
+            Node::InlineMaths(maths) => maths.write_html(builder),
+            Node::CodeBlock(code) => code.write_html(builder),
+            Node::MathsBlock(maths) => maths.write_html(builder),
+            Node::Mermaid(mermaid) => mermaid.write_html(builder),
+        }
+    }
+}
+
+/// The root of a Knots document
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Root {
-    pub contents: Vec<Box<dyn KnotsObject>>,
+    pub contents: Vec<Node>,
 }
 
 impl KnotsObject for Root {
@@ -20,9 +189,13 @@ impl KnotsObject for Root {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Title {
     pub contents: String,
     pub level: u8,
+    /// the explicit id given with `{#id}`, used as the anchor of the title
+    pub id: Option<String>,
+    pub span: Span,
 }
 
 impl KnotsObject for Title {
@@ -30,9 +203,11 @@ impl KnotsObject for Title {
         let title_container;
         let next_container;
 
-        let title = builder.add_title(self.level, &self.contents);
+        // the parser only makes levels 1 to 6, but a title can be built with any level
+        let level = self.level.clamp(1, 6);
+        let title = builder.add_title(level, &self.contents, self.id.as_deref());
 
-        match self.level {
+        match level {
             1 => {
                 title_container = "container-lvl1";
                 next_container = "container-lvl2";
@@ -42,34 +217,46 @@ impl KnotsObject for Title {
                 title_container = "container-lvl2";
                 next_container = "container";
             }
-            3 => {
+            _ => {
                 title_container = "container";
                 next_container = "container";
             }
-            _ => unreachable!(),
         }
 
-        let tag = format!("h{}", self.level);
-        let level_class = format!("lvl{}", self.level);
+        let tag = format!("h{}", level);
+        let level_class = format!("lvl{}", level);
 
-        builder.end_tag(); // </div>
+        // we can't switch containers inside a box or a quote
+        let nested = builder.is_nested();
+
+        if !nested {
+            builder.end_tag(); // </div>
+
+            // switch to the larger container
+            builder.start_tag("div", &[("class", title_container)]);
+        }
 
-        // switch to the larger container
-        builder.start_tag("div", &[("class", &title_container)]);
         builder.start_tag(&tag, &[("class", &level_class), ("id", &title.anchor)]);
         builder.start_tag("a", &[("href", &format!("#{}", &title.anchor))]);
         builder.write_content(&title.name);
         builder.write_content(LINK_SVG);
         builder.end_tag();
         builder.end_tag(); // </h2>
+
+        if nested {
+            return;
+        }
+
         builder.end_tag(); // </div>
         builder.start_tag("div", &[("class", next_container)]);
         builder.current_container = next_container.to_owned()
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Paragraph {
-    pub contents: Vec<Box<dyn KnotsObject>>,
+    pub contents: Vec<Node>,
+    pub span: Span,
 }
 impl KnotsObject for Paragraph {
     fn write_html(&self, builder: &mut Builder) {
@@ -79,123 +266,225 @@ impl KnotsObject for Paragraph {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct BlockQuote {
-    pub contents: Vec<Box<dyn KnotsObject>>,
+    pub contents: Vec<Node>,
+    pub span: Span,
 }
 
 impl KnotsObject for BlockQuote {
     fn write_html(&self, builder: &mut Builder) {
         builder.start_tag("blockquote", &[]);
-        builder.write_knots_objects(&self.contents);
+        builder.write_nested_knots_objects(&self.contents);
         builder.end_tag(); // </blockquote>
     }
 }
 
-const INFO_SVG: &str = include_str!("../icons/info.svg");
-pub struct InfoBox {
-    pub contents: Vec<Box<dyn KnotsObject>>,
+/// The icons of the built-in admonition kinds
+const ADMONITION_ICONS: &[(&str, &str)] = &[
+    ("info", include_str!("../icons/info.svg")),
+    ("warning", include_str!("../icons/danger.svg")),
+    ("error", include_str!("../icons/close-o.svg")),
+    ("note", include_str!("../icons/pen.svg")),
+    ("tip", include_str!("../icons/bulb.svg")),
+    ("success", include_str!("../icons/check-o.svg")),
+    ("question", include_str!("../icons/question-o.svg")),
+    ("example", include_str!("../icons/list.svg")),
+    ("definition", include_str!("../icons/book.svg")),
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Admonition {
+    /// the kind of admonition, like `info` or `tip`
+    pub kind: String,
+    /// the title shown above the contents
+    pub title: Option<Vec<Node>>,
+    pub contents: Vec<Node>,
+    pub span: Span,
 }
 
-impl KnotsObject for InfoBox {
+impl KnotsObject for Admonition {
     fn write_html(&self, builder: &mut Builder) {
-        builder.start_tag("div", &[("class", "infobox")]);
-        builder.write_content(INFO_SVG);
-        builder.start_tag("p", &[]);
-        builder.write_knots_objects(&self.contents);
-        builder.end_tag(); // </p>
-        builder.end_tag(); // </div>
-    }
-}
+        // the kinds given in the options take precedence over the built-in ones
+        let icon = match builder
+            .admonition_kinds
+            .iter()
+            .find(|kind| kind.name == self.kind)
+        {
+            Some(kind) => kind.icon.clone(),
+            None => match ADMONITION_ICONS.iter().find(|(kind, _)| *kind == self.kind) {
+                Some((_, icon)) => (*icon).to_owned(),
+                None => {
+                    let known_kinds = ADMONITION_ICONS
+                        .iter()
+                        .map(|(kind, _)| *kind)
+                        .chain(
+                            builder
+                                .admonition_kinds
+                                .iter()
+                                .map(|kind| kind.name.as_str()),
+                        )
+                        .collect::<Vec<_>>()
+                        .join(", ");
+
+                    builder.add_warning(
+                        self.span,
+                        Construct::Admonition,
+                        format!(
+                            "unknown admonition kind {}, use one of {} or register it in the options",
+                            self.kind, known_kinds
+                        ),
+                    );
+                    ADMONITION_ICONS[0].1.to_owned()
+                }
+            },
+        };
 
-const WARNING_SVG: &str = include_str!("../icons/danger.svg");
+        let class = format!("admonition admonition-{}", self.kind);
+        builder.start_tag("div", &[("class", &class)]);
+        builder.write_content(&icon);
+        builder.start_tag("div", &[("class", "box-contents")]);
 
-pub struct WarningBox {
-    pub contents: Vec<Box<dyn KnotsObject>>,
-}
+        if let Some(title) = &self.title {
+            builder.start_tag("p", &[("class", "admonition-title")]);
+            builder.write_knots_objects(title);
+            builder.end_tag(); // </p>
+        }
 
-impl KnotsObject for WarningBox {
-    fn write_html(&self, builder: &mut Builder) {
-        builder.start_tag("div", &[("class", "warningbox")]);
-        builder.write_content(WARNING_SVG);
-        builder.start_tag("p", &[]);
-        builder.write_knots_objects(&self.contents);
-        builder.end_tag(); // </p>
+        builder.write_nested_knots_objects(&self.contents);
+        builder.end_tag(); // </div>
         builder.end_tag(); // </div>
     }
 }
 
-const ERROR_SVG: &str = include_str!("../icons/close-o.svg");
-pub struct ErrorBox {
-    pub contents: Vec<Box<dyn KnotsObject>>,
-}
-
-impl KnotsObject for ErrorBox {
-    fn write_html(&self, builder: &mut Builder) {
-        builder.start_tag("div", &[("class", "errorbox")]);
-        builder.write_content(ERROR_SVG);
-        builder.start_tag("p", &[]);
-        builder.write_knots_objects(&self.contents);
-        builder.end_tag(); // </p>
-        builder.end_tag(); // </div>
-    }
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ListItem {
+    pub contents: Vec<Node>,
+    /// whether the task of this item is done, `None` if the item isn't a task
+    pub checked: Option<bool>,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct List {
-    pub contents: Vec<Vec<Box<dyn KnotsObject>>>,
+    pub contents: Vec<ListItem>,
+    /// the number of the first item of an ordered list, `None` for a bullet list
+    pub start: Option<usize>,
+    pub span: Span,
 }
 
 impl KnotsObject for List {
     fn write_html(&self, builder: &mut Builder) {
-        builder.start_tag("ul", &[]);
+        match self.start {
+            Some(start) => builder.start_tag("ol", &[("start", &start.to_string())]),
+            None => builder.start_tag("ul", &[]),
+        }
 
         for list_item in &self.contents {
-            builder.start_tag("li", &[]);
-            builder.write_knots_objects(&list_item);
+            match list_item.checked {
+                Some(checked) => {
+                    builder.add_task(checked);
+                    builder.start_tag("li", &[("class", "task")]);
+
+                    if checked {
+                        builder.orphan_tag(
+                            "input",
+                            &[("type", "checkbox"), ("disabled", ""), ("checked", "")],
+                        );
+                    } else {
+                        builder.orphan_tag("input", &[("type", "checkbox"), ("disabled", "")]);
+                    }
+                }
+
+                None => builder.start_tag("li", &[]),
+            }
+
+            // the code blocks of an item can't switch to a wider container
+            builder.write_nested_knots_objects(&list_item.contents);
             builder.end_tag(); // </li>
         }
 
-        builder.end_tag(); // </ul>
+        builder.end_tag(); // </ul> or </ol>
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Table {
-    pub header: Vec<Vec<Box<dyn KnotsObject>>>,
+    pub header: Vec<Vec<Node>>,
     // yeah, that's a lot of vec
-    pub rows: Vec<Vec<Vec<Box<dyn KnotsObject>>>>,
+    pub rows: Vec<Vec<Vec<Node>>>,
+    /// the alignment of each column, given with colons in the delimiter row
+    pub alignments: Vec<Option<Alignment>>,
+    /// the caption given with `Table: caption`
+    pub caption: Option<Vec<Node>>,
+    /// the explicit id given with `{#id}` after the caption
+    pub id: Option<String>,
+    pub span: Span,
 }
 
 impl KnotsObject for Table {
     fn write_html(&self, builder: &mut Builder) {
-        builder.start_tag("table", &[]);
+        // only tables with a caption or an id are numbered
+        if self.caption.is_none() && self.id.is_none() {
+            builder.start_tag("table", &[]);
+        } else {
+            let (number, anchor) = builder.add_table(self.id.as_deref());
+            builder.start_tag("table", &[("id", &anchor)]);
+            builder.start_tag("caption", &[]);
+            builder.inline_tag(
+                "span",
+                &[("class", "caption-label")],
+                &format!("Table {}", number),
+            );
+
+            if let Some(caption) = &self.caption {
+                builder.write_knots_objects(caption);
+            }
+
+            builder.end_tag(); // </caption>
+        }
+
+        let styles: Vec<_> = self
+            .alignments
+            .iter()
+            .map(|alignment| alignment.map(|alignment| format!("text-align: {}", alignment.name())))
+            .collect();
+
+        // the cells of the aligned columns are styled
+        let attributes = |column: usize| match styles.get(column) {
+            Some(Some(style)) => vec![("style", style.as_str())],
+            _ => Vec::new(),
+        };
 
         builder.start_tag("tr", &[]);
 
-        for cell in &self.header {
-            builder.start_tag("th", &[]);
+        for (column, cell) in self.header.iter().enumerate() {
+            builder.start_tag("th", &attributes(column));
             builder.write_knots_objects(cell);
-            builder.end_tag(); // </td>
+            builder.end_tag(); // </th>
         }
 
-        builder.end_tag(); // </th>
+        builder.end_tag(); // </tr>
 
         for row in &self.rows {
             builder.start_tag("tr", &[]);
 
-            for cell in row {
-                builder.start_tag("td", &[]);
+            for (column, cell) in row.iter().enumerate() {
+                builder.start_tag("td", &attributes(column));
                 builder.write_knots_objects(cell);
                 builder.end_tag(); // </td>
             }
 
-            builder.end_tag() // </tr>
+            builder.end_tag(); // </tr>
         }
 
         builder.end_tag(); // </table>
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct BasicText {
     pub contents: String,
+    pub span: Span,
 }
 
 impl KnotsObject for BasicText {
@@ -204,8 +493,10 @@ impl KnotsObject for BasicText {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Italic {
-    pub contents: Vec<Box<dyn KnotsObject>>,
+    pub contents: Vec<Node>,
+    pub span: Span,
 }
 
 impl KnotsObject for Italic {
@@ -216,8 +507,10 @@ impl KnotsObject for Italic {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Bold {
-    pub contents: Vec<Box<dyn KnotsObject>>,
+    pub contents: Vec<Node>,
+    pub span: Span,
 }
 
 impl KnotsObject for Bold {
@@ -228,38 +521,398 @@ impl KnotsObject for Bold {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Strikethrough {
+    pub contents: Vec<Node>,
+    pub span: Span,
+}
+
+impl KnotsObject for Strikethrough {
+    fn write_html(&self, builder: &mut Builder) {
+        builder.start_tag("del", &[]);
+        builder.write_knots_objects(&self.contents);
+        builder.end_tag() // </del>
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Highlight {
+    pub contents: Vec<Node>,
+    pub span: Span,
+}
+
+impl KnotsObject for Highlight {
+    fn write_html(&self, builder: &mut Builder) {
+        builder.start_tag("mark", &[]);
+        builder.write_knots_objects(&self.contents);
+        builder.end_tag() // </mark>
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Subscript {
+    pub contents: String,
+    pub span: Span,
+}
+
+impl KnotsObject for Subscript {
+    fn write_html(&self, builder: &mut Builder) {
+        // a subscript is part of the word before it, like in H~2~O
+        builder.write_glued(&format!("<sub>{}</sub>", escape_html(&self.contents)));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Superscript {
+    pub contents: String,
+    pub span: Span,
+}
+
+impl KnotsObject for Superscript {
+    fn write_html(&self, builder: &mut Builder) {
+        builder.write_glued(&format!("<sup>{}</sup>", escape_html(&self.contents)));
+    }
+}
+
+/// A key of the keyboard, like `[[Ctrl]]`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Keyboard {
+    pub key: String,
+    pub span: Span,
+}
+
+impl KnotsObject for Keyboard {
+    fn write_html(&self, builder: &mut Builder) {
+        // so shortcuts like [[Ctrl]]+[[C]] stay together
+        builder.write_glued(&format!("<kbd>{}</kbd>", escape_html(&self.key)));
+    }
+}
+
 const LINK_SVG: &str = include_str!("../icons/link.svg");
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Link {
-    pub name: String,
+    pub contents: Vec<Node>,
     pub link: String,
+    /// the title given with `"title"` after the url, shown when hovering the link
+    pub title: Option<String>,
+    /// the label of a reference link like `[text][label]`.
+    /// The link and the title of its definition are filled in once the document is parsed.
+    pub reference: Option<String>,
+    pub span: Span,
 }
 
 impl KnotsObject for Link {
     fn write_html(&self, builder: &mut Builder) {
-        builder.inline_tag("a", &[("href", &self.link), ("class", "link")], &self.name);
+        let escape_attribute = |value: &str| escape_html(value).replace('"', "&quot;");
+        let link = escape_attribute(&self.link);
+        let title = self.title.as_deref().map(escape_attribute);
+
+        let mut attributes = vec![("href", link.as_str()), ("class", "link")];
+        if let Some(title) = &title {
+            attributes.push(("title", title));
+        }
+
+        builder.start_tag("a", &attributes);
+        builder.write_knots_objects(&self.contents);
+        builder.end_tag(); // </a>
+    }
+}
+
+/// The target of the reference links with this label, like `[knots]: https://github.com "Knots"`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LinkDefinition {
+    pub label: String,
+    pub link: String,
+    pub title: Option<String>,
+    pub span: Span,
+}
+
+impl KnotsObject for LinkDefinition {
+    // the definition is only used by the links referencing it
+    fn write_html(&self, _builder: &mut Builder) {}
+}
+
+/// Returns the anchor of a reference to a footnote
+fn footnote_reference_anchor(number: usize, reference: usize) -> String {
+    match reference {
+        1 => format!("fnref-{}", number),
+        _ => format!("fnref-{}-{}", number, reference),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FootnoteReference {
+    pub id: String,
+    pub span: Span,
+}
+
+impl KnotsObject for FootnoteReference {
+    fn write_html(&self, builder: &mut Builder) {
+        let (number, reference) = builder.add_footnote_reference(&self.id);
+        let anchor = footnote_reference_anchor(number, reference);
+
+        builder.start_tag("sup", &[("class", "footnote-ref"), ("id", &anchor)]);
+        builder.inline_tag(
+            "a",
+            &[("href", &format!("#fn-{}", number))],
+            &number.to_string(),
+        );
+        builder.end_tag(); // </sup>
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FootnoteDefinition {
+    pub id: String,
+    pub contents: Vec<Node>,
+    pub span: Span,
+}
+
+impl FootnoteDefinition {
+    /// Writes this footnote in the footnotes section, with links back to its references
+    pub(crate) fn write_footnote(&self, builder: &mut Builder, number: usize, references: usize) {
+        builder.start_tag("li", &[("id", &format!("fn-{}", number))]);
+
+        // put the links back at the end of the last paragraph
+        let (others, last_contents) = match self.contents.split_last() {
+            Some((Node::Paragraph(last), others)) => (others, &last.contents[..]),
+            _ => (&self.contents[..], &[][..]),
+        };
+
+        builder.write_knots_objects(others);
+        builder.start_tag("p", &[]);
+        builder.write_knots_objects(last_contents);
+
+        for reference in 1..=references {
+            builder.inline_tag(
+                "a",
+                &[
+                    (
+                        "href",
+                        &format!("#{}", footnote_reference_anchor(number, reference)),
+                    ),
+                    ("class", "footnote-backref"),
+                ],
+                "&#8617;",
+            );
+        }
+
+        builder.end_tag(); // </p>
+        builder.end_tag(); // </li>
     }
 }
 
+impl KnotsObject for FootnoteDefinition {
+    // footnotes are written at the end of the document
+    fn write_html(&self, _builder: &mut Builder) {}
+}
+
+/// A reference to something with an explicit id, like `@ref(intro)`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CrossReference {
+    pub id: String,
+    pub span: Span,
+}
+
+impl KnotsObject for CrossReference {
+    fn write_html(&self, builder: &mut Builder) {
+        builder.write_reference(&self.id);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Image {
     pub alt: String,
     pub link: String,
+    /// the width given with `{width=...}`, in pixels or with a unit
+    pub width: Option<String>,
+    /// the height given with `{height=...}`, in pixels or with a unit
+    pub height: Option<String>,
+    /// the placement given with `{align=...}`, the text flows around left and right images
+    pub align: Option<Alignment>,
+    /// the caption given with `Figure: caption`
+    pub caption: Option<Vec<Node>>,
+    /// the explicit id given with `{#id}` after the caption
+    pub id: Option<String>,
+    pub span: Span,
+}
+
+impl Image {
+    /// Writes the image itself, as an `<img>`, an inline SVG,
+    /// or a placeholder if it can't be read
+    fn write_image(&self, builder: &mut Builder, style: &str) {
+        let mut attributes = vec![("alt", self.alt.as_str())];
+
+        if !style.is_empty() {
+            attributes.push(("style", style));
+        }
+
+        let (mut file, source) =
+            if self.link.starts_with("http://") || self.link.starts_with("https://") {
+                // include directly the link if it's from internet, unless it must work offline
+                if !builder.embed_remote {
+                    attributes.push(("src", &self.link));
+                    builder.orphan_tag("img", &attributes);
+                    return;
+                }
+
+                let cache_dir = builder.cache_dir.clone();
+
+                match fetch_remote_image(&self.link, cache_dir.as_deref()) {
+                    Ok(file) => (file, self.link.clone()),
+                    Err(err) => {
+                        // the link still works once back online, and shows the alt text until then
+                        builder.add_warning(
+                            self.span,
+                            Construct::Image,
+                            format!(
+                                "unable to download this image, it's linked instead: {}",
+                                err
+                            ),
+                        );
+
+                        attributes.push(("src", &self.link));
+                        builder.orphan_tag("img", &attributes);
+                        return;
+                    }
+                }
+            } else {
+                // else if it's from the disk load it
+                let path = builder.resolve_path(&self.link);
+
+                match std::fs::read(&path) {
+                    Ok(file) => (file, path.display().to_string()),
+                    Err(err) => {
+                        builder.add_warning(
+                            self.span,
+                            Construct::Image,
+                            format!("unable to read the image {}: {}", path.display(), err),
+                        );
+
+                        attributes[0] = ("class", "missing-image");
+                        builder.inline_tag(
+                            "div",
+                            &attributes,
+                            &format!("Missing image: {}", escape_html(&self.link)),
+                        );
+                        return;
+                    }
+                }
+            };
+
+        let mut mime_type = sniff_image_type(&file).unwrap_or("application/octet-stream");
+
+        if let Some(settings) = builder.image_optimisation.clone() {
+            let cache_dir = builder.cache_dir.clone();
+
+            match optimise_image(&file, mime_type, &settings, cache_dir.as_deref()) {
+                Ok(Some((optimised, optimised_type))) => {
+                    file = optimised;
+                    mime_type = optimised_type;
+                }
+                Ok(None) => (),
+                // the original image is still better than nothing
+                Err(err) => builder.add_warning(
+                    self.span,
+                    Construct::Image,
+                    format!("unable to optimise the image {}: {}", source, err),
+                ),
+            }
+        }
+
+        // SVG images are included as markup when nothing unsafe had to be removed from them,
+        // otherwise they're embedded like the others:
+        // browsers don't run their scripts nor apply their styles to the page in an <img>
+        if mime_type == "image/svg+xml" {
+            if let Some(svg) = sanitize_svg(&file).filter(|svg| !svg.removed) {
+                attributes[0] = ("aria-label", &self.alt);
+                attributes.push(("class", "svg-image"));
+                attributes.push(("role", "img"));
+
+                builder.start_tag("div", &attributes);
+                builder.write_content(&svg.markup);
+                builder.end_tag(); // </div>
+                return;
+            }
+        }
+
+        let src = format!("data:{};base64,{}", mime_type, encode(&file));
+        attributes.push(("src", &src));
+        builder.orphan_tag("img", &attributes);
+    }
 }
 
 impl KnotsObject for Image {
     fn write_html(&self, builder: &mut Builder) {
-        builder.end_tag(); // </div>
-        builder.start_tag("div", &[("class", "container-lg")]);
+        // lengths without a unit are in pixels
+        let css_length = |length: &str| {
+            if length.bytes().all(|b| b.is_ascii_digit()) {
+                format!("{}px", length)
+            } else {
+                length.to_owned()
+            }
+        };
+
+        let mut style = String::new();
+
+        if let Some(width) = &self.width {
+            style += &format!("width: {};", css_length(width));
+        }
+
+        if let Some(height) = &self.height {
+            style += &format!("height: {};", css_length(height));
+        }
+
+        // only images without any layout are shown as wide as possible
+        let wide = !builder.is_nested()
+            && self.width.is_none()
+            && self.height.is_none()
+            && self.align.is_none();
+
+        if wide {
+            builder.end_tag(); // </div>
+            builder.start_tag("div", &[("class", "container-lg")]);
+        }
+
+        // only images with a caption or an id are numbered
+        let numbered = self.caption.is_some() || self.id.is_some();
 
-        if self.link.starts_with("http://") || self.link.starts_with("https://") {
-            // include directly the link if it's from internet
-            builder.orphan_tag("img", &[("alt", &self.alt), ("src", &self.link)]);
+        if !numbered && self.align.is_none() {
+            self.write_image(builder, &style);
         } else {
-            // else if it's from the disk load it as base64
-            let file = std::fs::read(&self.link).expect("Unable to open image");
-            let base64_img = format!("data:application/octet-stream;base64,{}", encode(file));
-            builder.orphan_tag("img", &[("alt", &self.alt), ("src", &base64_img)]);
-        };
+            let class = match self.align {
+                Some(align) => format!("figure align-{}", align.name()),
+                None => "figure".to_owned(),
+            };
+
+            if numbered {
+                let (number, anchor) = builder.add_figure(self.id.as_deref());
+                builder.start_tag("figure", &[("id", &anchor), ("class", &class)]);
+                self.write_image(builder, &style);
+                builder.start_tag("figcaption", &[]);
+                builder.inline_tag(
+                    "span",
+                    &[("class", "caption-label")],
+                    &format!("Figure {}", number),
+                );
+
+                if let Some(caption) = &self.caption {
+                    builder.write_knots_objects(caption);
+                }
+
+                builder.end_tag(); // </figcaption>
+            } else {
+                builder.start_tag("figure", &[("class", &class)]);
+                self.write_image(builder, &style);
+            }
+
+            builder.end_tag(); // </figure>
+        }
+
+        if !wide {
+            return;
+        }
 
         builder.end_tag();
 
@@ -268,7 +921,10 @@ impl KnotsObject for Image {
     }
 }
 
-pub struct HorizontalRule {}
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HorizontalRule {
+    pub span: Span,
+}
 
 impl KnotsObject for HorizontalRule {
     fn write_html(&self, builder: &mut Builder) {
@@ -276,8 +932,10 @@ impl KnotsObject for HorizontalRule {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct InlineCode {
     pub contents: String,
+    pub span: Span,
 }
 
 impl KnotsObject for InlineCode {
@@ -290,8 +948,10 @@ impl KnotsObject for InlineCode {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct InlineMaths {
     pub contents: String,
+    pub span: Span,
 }
 
 impl KnotsObject for InlineMaths {
@@ -304,25 +964,114 @@ impl KnotsObject for InlineMaths {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct CodeBlock {
     pub contents: String,
     pub lang: String,
+    /// the title given with `title="..."`, like a file name
+    pub title: Option<String>,
+    /// are the lines numbered, with `linenos` or `start=N` ?
+    pub line_numbers: bool,
+    /// the number of the first line
+    pub start: usize,
+    /// the ranges of highlighted lines given with `{3-5,9}`, both ends included
+    pub highlighted: Vec<(usize, usize)>,
+    pub span: Span,
+}
+
+impl CodeBlock {
+    /// Is this line number in one of the highlighted ranges ?
+    fn is_highlighted(&self, line: usize) -> bool {
+        self.highlighted
+            .iter()
+            .any(|&(first, last)| first <= line && line <= last)
+    }
+
+    /// Writes the numbers of the lines, next to the code
+    fn write_line_numbers(&self, builder: &mut Builder) {
+        // prism trims the blank lines around the code, so they aren't numbered
+        let lines = self.contents.trim().lines().count().max(1);
+
+        let numbers = (self.start..self.start + lines)
+            .map(|line| match self.is_highlighted(line) {
+                true => format!("<span class=\"highlighted\">{}</span>", line),
+                false => line.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        // the whitespaces in a <pre> are kept, so the numbers are written on their own
+        builder.inline_tag(
+            "span",
+            &[("class", "code-line-numbers"), ("aria-hidden", "true")],
+            &numbers,
+        );
+    }
 }
 
 impl KnotsObject for CodeBlock {
     fn write_html(&self, builder: &mut Builder) {
         builder.should_include_prism = true;
         builder.languages.insert(self.lang.clone());
+        let lang = canonical_language(&self.lang);
+
+        // switch to a container-lg div to have a wider code block,
+        // unless we're in a box or a quote
+        let nested = builder.is_nested();
+        if !nested {
+            builder.end_tag(); // </div>
+            builder.start_tag("div", &[("class", "container-lg")]);
+        }
 
-        // switch to a container-lg div to have a wider code block
-        builder.end_tag(); // </div>
-        builder.start_tag("div", &[("class", "container-lg")]);
+        if let Some(title) = &self.title {
+            builder.start_tag("figure", &[("class", "code-figure")]);
+            builder.inline_tag(
+                "figcaption",
+                &[("class", "code-title")],
+                &escape_html(title),
+            );
+        }
+
+        let mut pre_attributes = vec![(
+            "class",
+            match self.line_numbers {
+                true => "codeblock numbered",
+                false => "codeblock",
+            },
+        )];
+
+        // the highlighted lines are drawn by the line-highlight plugin of prism
+        let highlighted = self
+            .highlighted
+            .iter()
+            .map(|&(first, last)| match first == last {
+                true => first.to_string(),
+                false => format!("{}-{}", first, last),
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let offset = (self.start as isize - 1).to_string();
+        if !highlighted.is_empty() {
+            pre_attributes.push(("data-line", &highlighted));
+            pre_attributes.push(("data-line-offset", &offset));
+        }
 
-        builder.start_tag("pre", &[("class", "codeblock")]);
-        builder.start_tag("code", &[("class", &format!("language-{}", self.lang))]);
+        builder.start_tag("pre", &pre_attributes);
+        if self.line_numbers {
+            self.write_line_numbers(builder);
+        }
+        builder.start_tag("code", &[("class", &format!("language-{}", lang))]);
         builder.write_content(&escape_html(&self.contents));
-        builder.end_tag(); // </pre>
         builder.end_tag(); // </code>
+        builder.end_tag(); // </pre>
+
+        if self.title.is_some() {
+            builder.end_tag(); // </figure>
+        }
+
+        if nested {
+            return;
+        }
 
         // open another regular container after that
         builder.end_tag(); // </div>
@@ -331,8 +1080,12 @@ impl KnotsObject for CodeBlock {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct MathsBlock {
     pub contents: String,
+    /// the label given with `{#id}`, which numbers the equation
+    pub id: Option<String>,
+    pub span: Span,
 }
 
 impl KnotsObject for MathsBlock {
@@ -340,14 +1093,36 @@ impl KnotsObject for MathsBlock {
         builder.should_include_katex = true;
         builder.maths_blocks += 1;
         let el_id = format!("maths{}", builder.maths_blocks);
-        builder.start_tag("div", &[("id", &el_id), ("class", "mathsblock")]);
-        builder.end_tag(); // </div>
+
+        match &self.id {
+            // show the number of labelled equations on the right
+            Some(id) => {
+                let number = builder.add_equation(id);
+                builder.start_tag("div", &[("id", id), ("class", "equation")]);
+                builder.start_tag("div", &[("id", &el_id), ("class", "mathsblock")]);
+                builder.end_tag(); // </div>
+                builder.inline_tag(
+                    "span",
+                    &[("class", "equation-number")],
+                    &format!("({})", number),
+                );
+                builder.end_tag(); // </div>
+            }
+
+            None => {
+                builder.start_tag("div", &[("id", &el_id), ("class", "mathsblock")]);
+                builder.end_tag(); // </div>
+            }
+        }
+
         builder.write_katex_content(&self.contents, &el_id);
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Mermaid {
     pub contents: String,
+    pub span: Span,
 }
 
 impl KnotsObject for Mermaid {
@@ -358,3 +1133,258 @@ impl KnotsObject for Mermaid {
         builder.end_tag(); // </div>
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list_item(text: &str) -> ListItem {
+        ListItem {
+            contents: vec![Node::BasicText(BasicText {
+                contents: text.to_owned(),
+                span: Span::default(),
+            })],
+            checked: None,
+        }
+    }
+
+    #[test]
+    fn writes_the_start_of_ordered_lists() {
+        let list = List {
+            contents: vec![list_item("Three"), list_item("Four")],
+            start: Some(3),
+            span: Span::default(),
+        };
+
+        let mut builder = Builder::new();
+        list.write_html(&mut builder);
+        let html = builder.into_result();
+
+        assert!(html.starts_with("<ol start=\"3\">"));
+        assert_eq!(html.matches("<li>").count(), 2);
+    }
+
+    #[test]
+    fn numbers_labelled_equations() {
+        let mut builder = Builder::new();
+
+        for id in &[Some("energy"), None, Some("force")] {
+            let maths = MathsBlock {
+                contents: "x".to_owned(),
+                id: id.map(str::to_owned),
+                span: Span::default(),
+            };
+            maths.write_html(&mut builder);
+        }
+        builder.write_reference("force");
+
+        let html = builder.into_result();
+        assert!(html.contains("<div id=\"energy\" class=\"equation\">"));
+        assert!(html.contains("<span class=\"equation-number\">(1)</span>"));
+        assert!(html.contains("<span class=\"equation-number\">(2)</span>"));
+        assert_eq!(html.matches("equation-number").count(), 2);
+        assert!(html.contains("<a href=\"#force\" class=\"ref\">(2)</a>"));
+    }
+
+    #[test]
+    fn writes_the_size_and_alignment_of_figures() {
+        let dir = std::env::temp_dir().join(format!("knots-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("sized.svg"), b"<svg></svg>").unwrap();
+
+        let image = Image {
+            alt: "A sized image".to_owned(),
+            link: "sized.svg".to_owned(),
+            width: Some("300".to_owned()),
+            height: Some("50%".to_owned()),
+            align: Some(Alignment::Right),
+            caption: None,
+            id: Some("sized".to_owned()),
+            span: Span::default(),
+        };
+
+        let mut builder = Builder::new();
+        builder.asset_root = dir;
+        image.write_html(&mut builder);
+        let html = builder.into_result();
+
+        assert!(html.starts_with("<figure id=\"sized\" class=\"figure align-right\">"));
+        assert!(html.contains("style=\"width: 300px;height: 50%;\""));
+        assert!(html.contains("<span class=\"caption-label\">Figure 1</span>"));
+    }
+
+    /// Writes a local image with these contents, returning the HTML
+    fn write_local_image(file_name: &str, contents: &[u8]) -> String {
+        let dir = std::env::temp_dir().join(format!("knots-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(file_name), contents).unwrap();
+
+        let image = Image {
+            alt: "An image".to_owned(),
+            link: file_name.to_owned(),
+            width: None,
+            height: None,
+            align: None,
+            caption: None,
+            id: None,
+            span: Span::default(),
+        };
+
+        let mut builder = Builder::new();
+        builder.asset_root = dir;
+        image.write_image(&mut builder, "");
+        builder.into_result()
+    }
+
+    #[test]
+    fn embeds_images_with_their_real_type() {
+        let html = write_local_image("photo.jpg", b"GIF89a\x01\0\x01\0\0\0\0;");
+        assert!(html.contains("src=\"data:image/gif;base64,"));
+
+        // unknown types are still embedded
+        let html = write_local_image("notes.png", b"not an image");
+        assert!(html.contains("src=\"data:application/octet-stream;base64,"));
+    }
+
+    #[test]
+    fn escapes_link_attributes() {
+        let link = Link {
+            contents: vec![Node::BasicText(BasicText {
+                contents: "Quotes".to_owned(),
+                span: Span::default(),
+            })],
+            link: "https://a.org/?q=\"x\"&y=<z>".to_owned(),
+            title: Some("Say \"hi\"".to_owned()),
+            reference: None,
+            span: Span::default(),
+        };
+
+        let mut builder = Builder::new();
+        link.write_html(&mut builder);
+
+        assert!(builder.into_result().starts_with(
+            "<a href=\"https://a.org/?q=&quot;x&quot;&amp;y=&lt;z&gt;\" class=\"link\" title=\"Say &quot;hi&quot;\">"
+        ));
+    }
+
+    #[test]
+    fn leaves_the_cells_beyond_the_alignments_unstyled() {
+        let cell = |text: &str| {
+            vec![Node::BasicText(BasicText {
+                contents: text.to_owned(),
+                span: Span::default(),
+            })]
+        };
+        let table = Table {
+            header: vec![cell("a"), cell("b")],
+            rows: vec![vec![cell("c"), cell("d"), cell("e")]],
+            alignments: vec![Some(Alignment::Right)],
+            caption: None,
+            id: None,
+            span: Span::default(),
+        };
+
+        let mut builder = Builder::new();
+        table.write_html(&mut builder);
+        let html = builder.into_result();
+
+        assert_eq!(html.matches("style=\"text-align: right\"").count(), 2);
+        assert_eq!(html.matches("<td>").count(), 2);
+        assert_eq!(html.matches("</tr>").count(), 2);
+    }
+
+    #[test]
+    fn clamps_the_level_of_titles() {
+        let mut builder = Builder::new();
+        // titles close the container they're in
+        builder.start_tag("div", &[("class", "container-lvl1")]);
+        for level in &[0, 7] {
+            Title {
+                contents: "Title".to_owned(),
+                level: *level,
+                id: None,
+                span: Span::default(),
+            }
+            .write_html(&mut builder);
+        }
+        builder.end_tag();
+        let html = builder.into_result();
+
+        assert!(html.contains("<h1 class=\"lvl1\""));
+        assert!(html.contains("<h6 class=\"lvl6\""));
+    }
+
+    #[test]
+    fn escapes_subscripts_and_superscripts() {
+        let mut builder = Builder::new();
+        Subscript {
+            contents: "<b>".to_owned(),
+            span: Span::default(),
+        }
+        .write_html(&mut builder);
+        Superscript {
+            contents: "a&b".to_owned(),
+            span: Span::default(),
+        }
+        .write_html(&mut builder);
+        let html = builder.into_result();
+
+        assert!(html.contains("<sub>&lt;b&gt;</sub>"));
+        assert!(html.contains("<sup>a&amp;b</sup>"));
+    }
+
+    #[test]
+    fn reports_unknown_admonition_kinds() {
+        let admonition = Admonition {
+            kind: "tipp".to_owned(),
+            title: None,
+            contents: Vec::new(),
+            span: Span {
+                start: 4,
+                end: 20,
+                line: 2,
+                column: 1,
+            },
+        };
+
+        let mut builder = Builder::new();
+        admonition.write_html(&mut builder);
+
+        let warnings = builder.get_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].0, admonition.span);
+        assert_eq!(warnings[0].1, Construct::Admonition);
+        assert!(warnings[0].2.contains("unknown admonition kind tipp"));
+    }
+
+    #[test]
+    fn embeds_svg_event_handlers_as_images() {
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" /onload="alert(1)"></svg>"#;
+        let html = write_local_image("handler.svg", svg);
+
+        assert!(html.contains(&format!(
+            "src=\"data:image/svg+xml;base64,{}\"",
+            encode(&svg[..])
+        )));
+        assert!(!html.contains("onload"));
+    }
+
+    #[test]
+    fn embeds_svg_styles_as_images() {
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg"><style>body { display: none; }</style></svg>"#;
+        let html = write_local_image("style.svg", svg);
+
+        assert!(html.contains("src=\"data:image/svg+xml;base64,"));
+        assert!(!html.contains("<style>"));
+    }
+
+    #[test]
+    fn inlines_safe_svgs() {
+        let svg = b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"><circle r=\"4\"/></svg>\n";
+        let html = write_local_image("circle.svg", svg);
+
+        assert!(html.starts_with("<div aria-label=\"An image\" class=\"svg-image\" role=\"img\">"));
+        assert!(html.contains("<svg xmlns=\"http://www.w3.org/2000/svg\"><circle r=\"4\"/></svg>"));
+        assert!(!html.contains("<?xml"));
+    }
+}
@@ -0,0 +1,89 @@
+//! Knots is a superset of Markdown allowing you to take notes efficiently.
+//!
+//! This crate translates Knots documents to self-contained HTML pages:
+//!
+//! ```no_run
+//! let rendered = knots::transpile("# Hello *world*", "hello.md", &knots::KnotsOptions::default())
+//!     .expect("invalid Knots document");
+//!
+//! // the problems which didn't prevent the rendering, like missing images
+//! for diagnostic in &rendered.diagnostics {
+//!     eprintln!("{}", diagnostic);
+//! }
+//!
+//! let html = rendered.html;
+//! ```
+//!
+//! Use [`parse_str`] and [`render`] to look at the parsed document before rendering it.
+//! The document tree is made of [`knots_objects::Node`]s, which can be walked
+//! with the traits of the [`visitor`] module.
+
+mod builder;
+mod images;
+mod parser;
+mod prism_autoloader;
+mod svg;
+mod transpiler;
+mod utils;
+
+pub mod diagnostics;
+pub mod knots_objects;
+pub mod visitor;
+
+pub use diagnostics::ParseError;
+pub use images::{ImageFormat, ImageOptimisation};
+pub use parser::{parse_file, parse_str, ParseResult};
+pub use transpiler::{render, AdmonitionKind, KnotsOptions, Numbering, RenderResult};
+
+/// Transpiles a Knots document to a self-contained HTML page.
+/// `name` is used as the default title and in the diagnostics.
+pub fn transpile(
+    input: &str,
+    name: &str,
+    options: &KnotsOptions,
+) -> Result<RenderResult, ParseError> {
+    let parse_result = parse_str(input, name)?;
+    let mut diagnostics = parse_result.diagnostics.clone();
+    let mut rendered = render(parse_result, options);
+    diagnostics.append(&mut rendered.diagnostics);
+    rendered.diagnostics = diagnostics;
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transpiles_a_document() {
+        let rendered = transpile(
+            "%title Notes\n\nHello *world*\n",
+            "notes.md",
+            &KnotsOptions::default(),
+        )
+        .unwrap();
+
+        assert!(rendered.diagnostics.is_empty());
+        assert!(rendered.html.contains("<p id=\"doctitle\">\n\t\t\t\tNotes"));
+        assert!(rendered.html.contains("<i>\n\t\t\t\t\t\t\tworld"));
+    }
+
+    #[test]
+    fn returns_syntax_errors() {
+        match transpile("Some `code\n", "notes.md", &KnotsOptions::default()) {
+            Err(ParseError::Syntax(diagnostics)) => {
+                assert_eq!(diagnostics.len(), 1);
+                assert_eq!(diagnostics[0].file, "notes.md");
+            }
+            _ => panic!("the unclosed inline code should be reported"),
+        }
+    }
+
+    #[test]
+    fn returns_io_errors() {
+        match parse_file("missing/notes.md") {
+            Err(ParseError::Io { file, .. }) => assert_eq!(file, "missing/notes.md"),
+            _ => panic!("the missing file should be reported"),
+        }
+    }
+}
@@ -1,17 +1,16 @@
-mod builder;
-mod knots_objects;
-mod parser;
-mod prism_autoloader;
-mod transpiler;
-mod utils;
-
-use std::{ffi::OsStr, fs, path::Path, process};
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
 
 use gumdrop::Options;
 use headless_chrome::{
     browser::default_executable, protocol::page::PrintToPdfOptions, Browser, FetcherOptions,
     LaunchOptionsBuilder,
 };
+use knots::{AdmonitionKind, ImageOptimisation, KnotsOptions, Numbering};
 
 #[derive(Debug, Options)]
 struct MyOptions {
@@ -20,13 +19,80 @@ struct MyOptions {
 
     #[options(
         no_long,
-        help = "the output file (will only output a .pdf or a .html depending on the extension)"
+        help = "the output file (will only output a .pdf, a .html or a .json document tree depending on the extension)"
     )]
     output: Option<String>,
 
     #[options(no_short, help = "don't create a summary")]
     no_summary: bool,
 
+    #[options(
+        no_short,
+        help = "show the completion of the task lists of each section in the summary"
+    )]
+    task_progress: bool,
+
+    #[options(
+        no_short,
+        meta = "KIND,ICON,COLOUR",
+        help = "register an admonition kind with an svg icon file and a css colour, e.g tip,tip.svg,#80e27e"
+    )]
+    admonition: Vec<String>,
+
+    #[options(
+        no_short,
+        meta = "LEVELS",
+        help = "the numbering of each title level: roman, arabic, alpha, dotted or none, e.g roman,dotted,dotted"
+    )]
+    numbering: Option<String>,
+
+    #[options(
+        no_short,
+        meta = "DIR",
+        help = "the directory where the relative paths of the document start from, instead of the directory of the document"
+    )]
+    asset_root: Option<String>,
+
+    #[options(
+        no_short,
+        help = "downsize and re-encode the local images without their metadata, so the document is lighter"
+    )]
+    optimise_images: bool,
+
+    #[options(
+        no_short,
+        meta = "PX",
+        help = "optimise the images and downsize the ones wider than this (default: 1600)"
+    )]
+    max_image_width: Option<u32>,
+
+    #[options(
+        no_short,
+        meta = "QUALITY",
+        help = "optimise the images with this JPEG quality, from 1 to 100 (default: 80)"
+    )]
+    image_quality: Option<u8>,
+
+    #[options(
+        no_short,
+        meta = "FORMAT",
+        help = "optimise the images to this format: jpeg, or webp which is lossless (default: jpeg)"
+    )]
+    image_format: Option<String>,
+
+    #[options(
+        no_short,
+        help = "download the remote images to embed them, so the document works offline"
+    )]
+    embed_remote: bool,
+
+    #[options(
+        no_short,
+        meta = "DIR",
+        help = "where to keep the optimised and downloaded images between builds (default: .knots-cache next to the document)"
+    )]
+    cache_dir: Option<String>,
+
     #[options(
         no_short,
         help = "allow the download of a chrome copy to convert html to pdf, if no installation is found"
@@ -55,6 +121,7 @@ pub fn main() {
 
     let pdf_output;
     let html_output;
+    let mut json_output = None;
 
     if let Some(output) = opts.output {
         let ext = Path::new(&output)
@@ -65,6 +132,11 @@ pub fn main() {
         if ext == "pdf" {
             pdf_output = Some(output);
             html_output = None;
+        // output the document tree
+        } else if ext == "json" {
+            json_output = Some(output);
+            html_output = None;
+            pdf_output = None;
         // output html
         } else {
             html_output = Some(output);
@@ -85,16 +157,95 @@ pub fn main() {
         pdf_output = Some(format!("{}.pdf", file_name));
     }
 
-    let parse_result = parser::parse(&opts.input[0]).unwrap_or_else(|_| {
-        eprintln!("Unable to read input file");
+    let parse_result = knots::parse_file(&opts.input[0]).unwrap_or_else(|err| {
+        eprintln!("{}", err);
         process::exit(1);
     });
 
-    let user_opts = transpiler::KnotsOptions {
+    for diagnostic in &parse_result.diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+
+    if let Some(json) = json_output {
+        let tree = serde_json::to_string_pretty(&parse_result).unwrap();
+        fs::write(json, tree).unwrap_or_else(|_| {
+            eprintln!("Unable to write to json file");
+            process::exit(1);
+        });
+        return;
+    }
+
+    let admonitions = opts
+        .admonition
+        .iter()
+        .map(|admonition| parse_admonition_kind(admonition))
+        .collect();
+
+    let numbering = opts.numbering.map(|numbering| {
+        Numbering::parse_levels(&numbering).unwrap_or_else(|err| {
+            eprintln!("Invalid numbering: {}", err);
+            process::exit(1);
+        })
+    });
+
+    let image_optimisation = if opts.optimise_images
+        || opts.max_image_width.is_some()
+        || opts.image_quality.is_some()
+        || opts.image_format.is_some()
+    {
+        let default = ImageOptimisation::default();
+
+        let quality = opts.image_quality.unwrap_or(default.quality);
+        if !(1..=100).contains(&quality) {
+            eprintln!(
+                "Invalid image quality {}, it must be from 1 to 100",
+                quality
+            );
+            process::exit(1);
+        }
+
+        let format = opts.image_format.map_or(default.format, |format| {
+            format.parse().unwrap_or_else(|err| {
+                eprintln!("Invalid image format: {}", err);
+                process::exit(1);
+            })
+        });
+
+        Some(ImageOptimisation {
+            max_width: opts.max_image_width.unwrap_or(default.max_width),
+            quality,
+            format,
+        })
+    } else {
+        None
+    };
+
+    let input = &opts.input[0];
+    let cache_dir = opts.cache_dir.map(PathBuf::from).unwrap_or_else(|| {
+        Path::new(input)
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(".knots-cache")
+    });
+
+    let user_opts = KnotsOptions {
         summary: !opts.no_summary,
+        task_progress: opts.task_progress,
+        admonitions,
+        numbering,
+        asset_root: opts.asset_root.map(PathBuf::from),
+        image_optimisation,
+        embed_remote: opts.embed_remote,
+        cache_dir: Some(cache_dir),
     };
 
-    let result = transpiler::transpile(parse_result, user_opts);
+    let rendered = knots::render(parse_result, &user_opts);
+
+    for diagnostic in &rendered.diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+
+    let result = rendered.html;
 
     if let Some(html) = &html_output {
         fs::write(html, &result).unwrap_or_else(|_| {
@@ -165,3 +316,46 @@ pub fn main() {
             .unwrap_or_else(|_| eprintln!("Failed to remove the temporary html file"));
     }
 }
+
+/// Parses an admonition kind given as KIND,ICON,COLOUR
+fn parse_admonition_kind(arg: &str) -> AdmonitionKind {
+    let mut parts = arg.splitn(3, ',');
+
+    let (name, icon_file, colour) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(name), Some(icon_file), Some(colour)) => (name, icon_file, colour),
+        _ => {
+            eprintln!(
+                "Invalid admonition {}, it must look like KIND,ICON,COLOUR",
+                arg
+            );
+            process::exit(1);
+        }
+    };
+
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        eprintln!(
+            "Invalid admonition kind {}, it must only contain letters, digits and dashes",
+            name
+        );
+        process::exit(1);
+    }
+
+    if !AdmonitionKind::is_valid_colour(colour) {
+        eprintln!(
+            "Invalid admonition colour {}, it must look like #80e27e or be a colour name like teal",
+            colour
+        );
+        process::exit(1);
+    }
+
+    let icon = fs::read_to_string(icon_file).unwrap_or_else(|_| {
+        eprintln!("Unable to read the admonition icon {}", icon_file);
+        process::exit(1);
+    });
+
+    AdmonitionKind {
+        name: name.to_owned(),
+        icon,
+        colour: colour.to_owned(),
+    }
+}
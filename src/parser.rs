@@ -1,419 +1,2888 @@
-use std::{fs::read_to_string, process};
+use std::collections::{HashMap, HashSet};
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
 
+use crate::diagnostics::{Construct, Diagnostic, ParseError};
 use crate::knots_objects;
+use crate::transpiler::Numbering;
+use crate::utils::escape_html;
+use crate::visitor::{Visitor, VisitorMut};
 
-use super::knots_objects::KnotsObject;
+use super::knots_objects::{
+    Alignment, CrossReference, FootnoteDefinition, FootnoteReference, Image, Link, LinkDefinition,
+    ListItem, MathsBlock, Node, Root, Span, Table, Title,
+};
 use nom::{
     branch::alt,
     bytes::complete::is_a,
     bytes::complete::is_not,
     bytes::complete::tag,
     bytes::complete::take_until,
+    bytes::complete::take_while1,
     character::complete::alpha1,
     character::complete::alphanumeric1,
     character::complete::line_ending,
-    character::complete::multispace0,
     character::complete::not_line_ending,
     character::complete::space0,
-    character::complete::{none_of, space1},
-    combinator::{eof, peek},
-    combinator::{opt, recognize},
-    error::ParseError,
+    character::complete::{digit1, none_of, one_of, space1},
+    character::complete::{multispace0, multispace1},
+    combinator::{cut, eof, not, peek, value},
+    combinator::{map, map_res, opt, recognize, verify},
+    error::{ErrorKind, ParseError as NomParseError},
     multi::many0,
-    multi::{count, many1},
+    multi::{count, many1, many_m_n, many_till, separated_list1},
     sequence::delimited,
-    sequence::{pair, preceded, separated_pair, terminated},
+    sequence::{pair, preceded, separated_pair, terminated, tuple},
     AsChar, IResult, InputTakeAtPosition, Parser,
 };
+use serde::Serialize;
 
+/// The result of parsing a Knots document
+#[derive(Debug, Clone, Serialize)]
 pub struct ParseResult {
-    pub root_object: Box<dyn KnotsObject>,
+    /// the title given with `%title`, or the name of the document
     pub document_title: String,
+    /// the authors given with `%author`
     pub document_authors: Vec<String>,
+    /// the license given with `%license`
     pub document_license: Option<String>,
+    /// the numbering of the title levels given with `%numbering`
+    pub document_numbering: Option<Vec<Numbering>>,
+    /// the root of the parsed document
+    pub root_object: Root,
+    /// the name of the document, used in the diagnostics found while rendering it
+    #[serde(skip)]
+    pub name: String,
+    /// the source of the document, to quote it in these diagnostics
+    #[serde(skip)]
+    pub source: String,
+    /// the directory of the document file, where its relative paths start from
+    #[serde(skip)]
+    pub directory: Option<PathBuf>,
+    /// the problems which didn't prevent the document from being parsed, like unknown metadata
+    #[serde(skip)]
+    pub diagnostics: Vec<Diagnostic>,
 }
 
 /// Parses a .knots file
-pub fn parse(file_name: &str) -> Result<ParseResult, String> {
-    // parse the file
-    let input =
-        read_to_string(file_name).map_err(|_| format!("Failed to open file {}", file_name))?;
+pub fn parse_file(file_name: &str) -> Result<ParseResult, ParseError> {
+    let input = read_to_string(file_name).map_err(|error| ParseError::Io {
+        file: file_name.to_owned(),
+        error,
+    })?;
 
-    // start by getting all the variables
-    let (other, variables) = many0(var_pair)(&input).unwrap();
+    let mut parse_result = parse_str(&input, file_name)?;
+    parse_result.directory = Path::new(file_name).parent().map(Path::to_path_buf);
+
+    Ok(parse_result)
+}
 
+/// Parses a Knots document.
+/// `name` is used as the default title and in the diagnostics.
+pub fn parse_str(input: &str, name: &str) -> Result<ParseResult, ParseError> {
     let mut document_title = None;
     let mut document_license = None;
+    let mut document_numbering = None;
     let mut document_authors = Vec::new();
 
-    for (var_name, var_content) in variables {
+    let mut diagnostics = Vec::new();
+    let mut warnings = Vec::new();
+    let mut other = input;
+
+    // start by getting all the variables
+    while let Ok((next, (var_name, var_content))) = var_pair(other) {
         match var_name {
             "title" => document_title = Some(var_content.to_owned()),
             "author" => document_authors.push(var_content.to_owned()),
             "license" => document_license = Some(var_content.to_owned()),
-            _ => eprintln!("unknown metadata: {}", var_name),
+            "numbering" => match Numbering::parse_levels(var_content) {
+                Ok(numbering) => document_numbering = Some(numbering),
+                Err(err) => diagnostics.push(Diagnostic::new(
+                    name,
+                    input,
+                    input.len() - other.len(),
+                    Construct::Metadata,
+                    err,
+                )),
+            },
+            _ => warnings.push(Diagnostic::warning(
+                name,
+                input,
+                input.len() - other.len(),
+                Construct::Metadata,
+                format!(
+                    "unknown metadata %{}, use %title, %author, %license or %numbering",
+                    var_name
+                ),
+            )),
+        }
+
+        other = next;
+    }
+
+    let document_title = document_title.unwrap_or_else(|| name.to_owned());
+
+    let mut contents = Vec::new();
+
+    loop {
+        match any_object(other) {
+            Ok((next, object)) => {
+                contents.push(object);
+                other = next;
+            }
+
+            Err(_) => {
+                // only whitespaces are left, we're done
+                let (faulty, _) = multispace0::<_, ()>(other).unwrap_or((other, ""));
+                if faulty.is_empty() {
+                    break;
+                }
+
+                // report the error and try to parse what comes after
+                let failure = diagnose(faulty);
+                let offset = input.len() - faulty.len() + failure.offset;
+
+                diagnostics.push(Diagnostic::new(
+                    name,
+                    input,
+                    offset,
+                    failure.construct,
+                    failure.explanation,
+                ));
+
+                other = failure.resume;
+            }
         }
     }
 
-    let document_title = document_title.unwrap_or_else(|| file_name.to_owned());
+    if !diagnostics.is_empty() {
+        return Err(ParseError::Syntax(diagnostics));
+    }
 
-    let (other, contents) = delimited(multispace0, many0(any_object), multispace0)(other).unwrap();
+    let mut root_object = Root { contents };
+    TextMerger.visit_root_mut(&mut root_object);
+    SpanResolver::new(input).visit_root_mut(&mut root_object);
+
+    // every footnote must be defined once
+    let mut footnotes = FootnoteChecker::default();
+    footnotes.visit_root(&root_object);
+
+    for (id, span) in footnotes.references {
+        if !footnotes.definitions.contains(&id) {
+            diagnostics.push(Diagnostic::new(
+                name,
+                input,
+                span.start,
+                Construct::Footnote,
+                format!("the footnote [^{}] is never defined", id),
+            ));
+        }
+    }
 
-    let root_object = Box::new(knots_objects::Root { contents });
+    // and every explicit id must be unique, and referenced ids must exist
+    let mut ids = IdChecker::default();
+    ids.visit_root(&root_object);
+
+    for (id, span) in ids.duplicates {
+        diagnostics.push(Diagnostic::new(
+            name,
+            input,
+            span.start,
+            Construct::Id,
+            format!("the id {} is already used", id),
+        ));
+    }
 
-    if !other.is_empty() {
-        let first_errored_line = other.lines().next().unwrap();
+    for (id, span) in ids.reserved {
+        diagnostics.push(Diagnostic::new(
+            name,
+            input,
+            span.start,
+            Construct::Id,
+            format!(
+                "the id {} is reserved for what Knots numbers itself, use another one",
+                id
+            ),
+        ));
+    }
 
-        eprintln!(
-            "Parser failed! Incorrect syntax on this line:\n{}",
-            first_errored_line
-        );
+    for (id, span) in ids.references {
+        if !ids.ids.contains(&id) {
+            diagnostics.push(Diagnostic::new(
+                name,
+                input,
+                span.start,
+                Construct::CrossReference,
+                format!(
+                    "nothing has the id {}, add {{#{}}} to a title, a table, a figure or an equation",
+                    id, id
+                ),
+            ));
+        }
+    }
+
+    for (id, span) in footnotes.duplicates {
+        diagnostics.push(Diagnostic::new(
+            name,
+            input,
+            span.start,
+            Construct::Footnote,
+            format!("the footnote [^{}] is already defined", id),
+        ));
+    }
+
+    // the reference links get the url of their definition, which may come after them
+    let mut links = LinkResolver::default();
+    links.visit_root(&root_object);
+    links.visit_root_mut(&mut root_object);
+
+    for (label, span) in links.undefined {
+        diagnostics.push(Diagnostic::new(
+            name,
+            input,
+            span.start,
+            Construct::Link,
+            format!(
+                "the link [{}] is never defined, add a line like [{}]: https://…",
+                label, label
+            ),
+        ));
+    }
+
+    for (label, span) in links.duplicates {
+        diagnostics.push(Diagnostic::new(
+            name,
+            input,
+            span.start,
+            Construct::Link,
+            format!("the link [{}] is already defined", label),
+        ));
+    }
 
-        process::exit(1);
+    if !diagnostics.is_empty() {
+        diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+        return Err(ParseError::Syntax(diagnostics));
     }
 
     Ok(ParseResult {
-        root_object,
         document_title,
         document_authors,
         document_license,
+        document_numbering,
+        root_object,
+        name: name.to_owned(),
+        source: input.to_owned(),
+        directory: None,
+        diagnostics: warnings,
     })
 }
 
-/// Parses a raw string
-fn basic(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, contents) = many1(alt((
-        recognize(pair(tag("$"), is_a(" ?!.,;"))),
-        recognize(is_not("`*\r\n#_[$|")),
-    )))(input)?;
-    let raw = Box::new(knots_objects::BasicText {
-        contents: contents.into_iter().fold(String::new(), |acc, x| acc + x),
-    });
-
-    Ok((other, raw))
+/// Records the position of a node, from its input and what's left after it.
+/// Positions are first counted from the end of the document,
+/// since the parsers only see what's left of it.
+fn span(input: &str, other: &str) -> Span {
+    Span {
+        start: input.len(),
+        end: other.len(),
+        ..Span::default()
+    }
 }
 
-/// Parses an italic string using `*`
-fn italic1(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, contents) = delimited(tag("*"), many1(any_text_modifier), tag("*"))(input)?;
-    let italic_obj = Box::new(knots_objects::Italic { contents });
-    Ok((other, italic_obj))
-}
+/// Merges the consecutive pieces of text, like the ones around a literal `*` in "3 * 4",
+/// so they're written as one
+struct TextMerger;
+
+impl VisitorMut for TextMerger {
+    fn visit_nodes_mut(&mut self, nodes: &mut Vec<Node>) {
+        let mut merged: Vec<Node> = Vec::with_capacity(nodes.len());
+
+        for node in std::mem::take(nodes) {
+            match (merged.last_mut(), node) {
+                (Some(Node::BasicText(previous)), Node::BasicText(text)) => {
+                    previous.contents += &text.contents;
+                    previous.span.end = text.span.end;
+                }
+                (_, node) => merged.push(node),
+            }
+        }
 
-/// Parses an italic string using `_`
-fn italic2(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, contents) = delimited(tag("_"), many1(any_text_modifier), tag("_"))(input)?;
-    let italic_obj = Box::new(knots_objects::Italic { contents });
-    Ok((other, italic_obj))
-}
+        *nodes = merged;
 
-/// Parses a bold string using `**`
-fn bold1(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, contents) = delimited(tag("**"), many1(any_text_modifier), tag("**"))(input)?;
-    let bold_obj = Box::new(knots_objects::Bold { contents });
-    Ok((other, bold_obj))
+        for node in nodes.iter_mut() {
+            self.visit_node_mut(node);
+        }
+    }
 }
 
-/// Parses a bold string using `__`
-fn bold2(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, contents) = delimited(tag("__"), many1(any_text_modifier), tag("__"))(input)?;
-    let bold_obj = Box::new(knots_objects::Bold { contents });
-    Ok((other, bold_obj))
+/// Resolves the positions recorded while parsing to offsets, lines and columns
+struct SpanResolver<'a> {
+    source: &'a str,
+    /// the offsets where each line starts
+    line_starts: Vec<usize>,
 }
 
-/// Parses a link
-fn link(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, name) = delimited(tag("["), take_until("]"), tag("]"))(input)?;
-    let (other, link) = delimited(tag("("), take_until(")"), tag(")"))(other)?;
-
-    let link_obj = Box::new(knots_objects::Link {
-        name: name.to_owned(),
-        link: link.to_owned(),
-    });
+impl<'a> SpanResolver<'a> {
+    fn new(source: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
 
-    Ok((other, link_obj))
+        SpanResolver {
+            source,
+            line_starts,
+        }
+    }
 }
 
-/// Parses inline code
-fn inline_code(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, contents) = delimited(tag("`"), is_not("`"), tag("`"))(input)?;
-    let code_obj = Box::new(knots_objects::InlineCode {
-        contents: contents.to_owned(),
-    });
-    Ok((other, code_obj))
+impl VisitorMut for SpanResolver<'_> {
+    fn visit_nodes_mut(&mut self, nodes: &mut Vec<Node>) {
+        for node in nodes.iter_mut() {
+            let span = node.span_mut();
+            span.start = self.source.len() - span.start;
+            span.end = self.source.len() - span.end;
+
+            let line = match self.line_starts.binary_search(&span.start) {
+                Ok(line) => line,
+                Err(line) => line - 1,
+            };
+
+            span.line = line + 1;
+            span.column = self.source[self.line_starts[line]..span.start]
+                .chars()
+                .count()
+                + 1;
+
+            self.visit_node_mut(node);
+        }
+    }
 }
 
-/// Parses inline maths
-fn inline_maths(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    // do not match sequences with the first character after the dollar sign being punctuation or space.
-    // This is to avoid false positives when using text like "this costs 300$ at this store"
-    let (other, contents) = delimited(
-        pair(tag("$"), peek(none_of(" ?!.,;"))),
-        is_not("$"),
-        tag("$"),
-    )(input)?;
-    let maths_obj = Box::new(knots_objects::InlineMaths {
-        contents: contents.to_owned(),
-    });
-    Ok((other, maths_obj))
+/// Gathers the footnote references and definitions, to check that they match
+#[derive(Default)]
+struct FootnoteChecker {
+    /// the ids of the defined footnotes
+    definitions: HashSet<String>,
+    /// the footnotes defined more than once
+    duplicates: Vec<(String, Span)>,
+    /// the references to footnotes
+    references: Vec<(String, Span)>,
 }
 
-/// Parses as a bold, italic or raw string
-fn any_text_modifier(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    alt((
-        link,
-        bold1,
-        bold2,
-        italic1,
-        italic2,
-        inline_maths,
-        inline_code,
-        basic,
-    ))(input)
-}
+impl Visitor for FootnoteChecker {
+    fn visit_footnote_reference(&mut self, reference: &FootnoteReference) {
+        self.references.push((reference.id.clone(), reference.span));
+    }
 
-/// Matches if we're at the end of a line or of the file
-fn eolf(input: &str) -> IResult<&str, &str> {
-    alt((line_ending, eof))(input)
-}
+    fn visit_footnote_definition(&mut self, definition: &FootnoteDefinition) {
+        if !self.definitions.insert(definition.id.clone()) {
+            self.duplicates
+                .push((definition.id.clone(), definition.span));
+        }
 
-/// Strips whitespaces
-fn ws<I, O, E>(input: impl Parser<I, O, E>) -> impl FnMut(I) -> IResult<I, O, E>
-where
-    I: InputTakeAtPosition,
-    <I as InputTakeAtPosition>::Item: AsChar + Clone,
-    E: ParseError<I>,
-{
-    delimited(space0, input, space0)
+        self.visit_nodes(&definition.contents);
+    }
 }
 
-/// Parses a Knots variable name
-fn variable(input: &str) -> IResult<&str, &str> {
-    preceded(tag("%"), alpha1)(input)
+/// Gathers the explicit ids and the cross-references,
+/// to check that the ids are unique and that the references point to one of them
+#[derive(Default)]
+struct IdChecker {
+    /// the ids seen so far
+    ids: HashSet<String>,
+    /// the ids used more than once
+    duplicates: Vec<(String, Span)>,
+    /// the ids taking the form of the generated ones
+    reserved: Vec<(String, Span)>,
+    /// the cross-references
+    references: Vec<(String, Span)>,
 }
 
-/// Parses a variable and its contents like %title Hello world
-/// to a pair (var_name, var_contents), in this case ("title", "Hello World")
-fn var_pair(input: &str) -> IResult<&str, (&str, &str)> {
-    separated_pair(variable, space1, terminated(not_line_ending, eolf))(input)
+impl IdChecker {
+    fn add_id(&mut self, id: &Option<String>, span: Span) {
+        if let Some(id) = id {
+            if is_reserved_id(id) {
+                self.reserved.push((id.clone(), span));
+            } else if !self.ids.insert(id.clone()) {
+                self.duplicates.push((id.clone(), span));
+            }
+        }
+    }
 }
 
-/// Parses a paragraph of text
-fn paragraph(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, contents) = terminated(many1(any_text_modifier), eolf)(input)?;
-    let paragraph_obj = Box::new(knots_objects::Paragraph { contents });
-    Ok((other, paragraph_obj))
-}
+impl Visitor for IdChecker {
+    fn visit_title(&mut self, title: &Title) {
+        self.add_id(&title.id, title.span);
+    }
 
-/// Parses a Blockquote
-fn block_quote(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, contents) = delimited(tag(">"), many1(any_text_modifier), eolf)(input)?;
-    let quote_obj = Box::new(knots_objects::BlockQuote { contents });
+    fn visit_table(&mut self, table: &Table) {
+        self.add_id(&table.id, table.span);
 
-    Ok((other, quote_obj))
-}
+        if let Some(caption) = &table.caption {
+            self.visit_nodes(caption);
+        }
+
+        for cell in table.header.iter().chain(table.rows.iter().flatten()) {
+            self.visit_nodes(cell);
+        }
+    }
 
-/// Parses an info box
-fn info_box(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, contents) = delimited(tag("?>"), many1(any_text_modifier), eolf)(input)?;
-    let box_obj = Box::new(knots_objects::InfoBox { contents });
+    fn visit_image(&mut self, image: &Image) {
+        self.add_id(&image.id, image.span);
 
-    Ok((other, box_obj))
-}
+        if let Some(caption) = &image.caption {
+            self.visit_nodes(caption);
+        }
+    }
 
-/// Parses a warning box
-fn warning_box(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, contents) = delimited(tag("!>"), many1(any_text_modifier), eolf)(input)?;
-    let box_obj = Box::new(knots_objects::WarningBox { contents });
+    fn visit_maths_block(&mut self, maths: &MathsBlock) {
+        self.add_id(&maths.id, maths.span);
+    }
 
-    Ok((other, box_obj))
+    fn visit_cross_reference(&mut self, reference: &CrossReference) {
+        self.references.push((reference.id.clone(), reference.span));
+    }
 }
 
-/// Parses an error box
-fn error_box(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, contents) = delimited(tag("x>"), many1(any_text_modifier), eolf)(input)?;
-    let box_obj = Box::new(knots_objects::ErrorBox { contents });
-
-    Ok((other, box_obj))
+/// Is this id one of the ones given to what has no explicit id,
+/// like `fn-1` for the first footnote or `table-2` for the second table without id ?
+fn is_reserved_id(id: &str) -> bool {
+    let is_number = |text: &str| !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit());
+
+    // the second reference to the first footnote is fnref-1-2
+    let is_footnote_reference = |id: &str| {
+        id.strip_prefix("fnref-")
+            .is_some_and(|numbers| numbers.splitn(2, '-').all(is_number))
+    };
+
+    ["doctitle", "footnotes", "license"].contains(&id)
+        || is_footnote_reference(id)
+        || ["fn-", "table-", "figure-", "maths"]
+            .iter()
+            .any(|prefix| id.strip_prefix(prefix).is_some_and(is_number))
 }
 
-/// Parses an horizontal ruler
-fn horizontal_ruler(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, _) = delimited(
-        alt((tag("***"), tag("---"), tag("___"))),
-        many0(is_a("*_-")),
-        eolf,
-    )(input)?;
-    let hr_obj = Box::new(knots_objects::HorizontalRule {});
-    Ok((other, hr_obj))
+/// Gathers the link definitions, then gives their url and title to the reference links
+#[derive(Default)]
+struct LinkResolver {
+    /// the url and title of each label
+    definitions: HashMap<String, (String, Option<String>)>,
+    /// the labels defined more than once
+    duplicates: Vec<(String, Span)>,
+    /// the reference links whose label is never defined
+    undefined: Vec<(String, Span)>,
 }
 
-/// Parses a level 1 title
-fn lvl1_title(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, contents) = delimited(tag("#"), ws(not_line_ending), eolf)(input)?;
-    let title_obj = Box::new(knots_objects::Title {
-        contents: contents.to_owned(),
-        level: 1,
-    });
-    Ok((other, title_obj))
+impl Visitor for LinkResolver {
+    fn visit_link_definition(&mut self, definition: &LinkDefinition) {
+        if self.definitions.contains_key(&definition.label) {
+            self.duplicates
+                .push((definition.label.clone(), definition.span));
+        } else {
+            self.definitions.insert(
+                definition.label.clone(),
+                (definition.link.clone(), definition.title.clone()),
+            );
+        }
+    }
 }
 
-/// Parses a level 2 title
-fn lvl2_title(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, contents) = delimited(tag("##"), ws(not_line_ending), eolf)(input)?;
-    let title_obj = Box::new(knots_objects::Title {
-        contents: contents.to_owned(),
-        level: 2,
-    });
-    Ok((other, title_obj))
+impl VisitorMut for LinkResolver {
+    fn visit_link_mut(&mut self, link: &mut Link) {
+        if let Some(label) = &link.reference {
+            match self.definitions.get(label) {
+                Some((url, title)) => {
+                    link.link = url.clone();
+                    link.title = title.clone();
+                }
+                None => self.undefined.push((label.clone(), link.span)),
+            }
+        }
+
+        self.visit_nodes_mut(&mut link.contents);
+    }
 }
 
-/// Parses a level 3 title
-fn lvl3_title(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, contents) = delimited(tag("###"), ws(not_line_ending), eolf)(input)?;
-    let title_obj = Box::new(knots_objects::Title {
-        contents: contents.to_owned(),
-        level: 3,
-    });
-    Ok((other, title_obj))
+/// The reason why an object couldn't be parsed
+struct Failure<'a> {
+    /// where the failure happened, in bytes from the start of the faulty object
+    offset: usize,
+    /// the construct which failed
+    construct: Construct,
+    /// what went wrong
+    explanation: String,
+    /// where to resume parsing
+    resume: &'a str,
 }
 
-/// Parses a code block
-fn code_block(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, _) = tag("```")(input)?;
+/// Finds out why `any_object` failed on this input
+fn diagnose(input: &str) -> Failure<'_> {
+    let (line, next_lines) = split_line(input);
+
+    if let Ok((after_fence, fence)) = code_fence(line) {
+        // look for the line closing this code fence, which may be indented in a list item
+        let mut closing = next_lines;
+        while !closing.is_empty()
+            && code_fence_end(fence, "")(closing.trim_start_matches(&[' ', '\t'][..])).is_err()
+        {
+            closing = split_line(closing).1;
+        }
 
-    // try to read the language annotation if it exists
-    let (other, lang) = opt(alphanumeric1)(other)?;
-    let (other, _) = line_ending(other)?;
-    let (other, contents) = terminated(take_until("```"), tag("```"))(other)?;
+        // the code fence may be closed, but with an invalid annotation
+        if !closing.is_empty() {
+            let (_, resume) = split_line(closing);
+
+            // skip the language and the valid attributes to find the faulty part
+            let lang_length = after_fence
+                .find(|c| !is_language_char(c))
+                .unwrap_or(after_fence.len());
+            let (lang, mut rest) = after_fence.split_at(lang_length);
+            while let Ok((other, _)) = preceded(space1, code_attribute)(rest) {
+                rest = other;
+            }
+            let faulty = rest.trim_start();
+            let offset = fence.len() + after_fence.len() - faulty.len();
+
+            let explanation = if lang.is_empty() && !rest.starts_with(char::is_whitespace) {
+                format!(
+                    "the language annotation `{}` must only contain letters, digits and +-#._",
+                    faulty.split_whitespace().next().unwrap_or_default()
+                )
+            } else {
+                format!(
+                    "`{}` isn't a code block attribute, use title=\"...\", linenos, start=N or {{3-5,9}}",
+                    faulty.trim_end()
+                )
+            };
+
+            return Failure {
+                offset,
+                construct: Construct::CodeFence,
+                explanation,
+                resume,
+            };
+        }
 
-    let lang = lang.unwrap_or_default().to_lowercase();
+        return Failure {
+            offset: 0,
+            construct: Construct::CodeFence,
+            explanation: format!(
+                "this code fence is never closed, add a {} line after the code",
+                fence
+            ),
+            resume: "",
+        };
+    }
 
-    // if the language annotation is mermaid, render as a mermaid diagram
-    if lang == "mermaid" {
-        let mermaid_obj = Box::new(knots_objects::Mermaid {
-            contents: contents.to_owned(),
-        });
-        Ok((other, mermaid_obj))
+    if line.starts_with("$$") {
+        return Failure {
+            offset: 0,
+            construct: Construct::MathsBlock,
+            explanation: "this maths block is never closed, add a $$ after the formula".to_owned(),
+            resume: "",
+        };
+    }
 
-    // else it's a prism code block
+    if looks_like_table(line, next_lines) {
+        return diagnose_table(input);
+    }
+
+    if line.starts_with(":::") {
+        return diagnose_admonition(input);
+    }
+
+    if line.starts_with("![") {
+        if let Some(attributes) = line.find("){") {
+            return Failure {
+                offset: attributes + 1,
+                construct: Construct::Image,
+                explanation: "the attributes of an image must look like {width=300 height=200 align=left}, and its id goes after the caption like Figure: caption {#id}".to_owned(),
+                resume: next_lines,
+            };
+        }
+    }
+
+    if line.starts_with("#######") {
+        return Failure {
+            offset: 6,
+            construct: Construct::Title,
+            explanation: "titles can't be deeper than 6 levels".to_owned(),
+            resume: next_lines,
+        };
+    }
+
+    let prefix = [">", "?>", "!>", "x>"]
+        .iter()
+        .find(|prefix| line.starts_with(*prefix));
+
+    if let Some(lines) = prefix.and_then(|prefix| PrefixedLines::new(input, prefix)) {
+        return diagnose_prefixed(&lines);
+    }
+
+    let (construct, prefix_len) = if let Ok((item, _)) = list_marker(line) {
+        (Construct::List, line.len() - item.len())
+    } else if let Ok((text, _)) = footnote_definition_start(line) {
+        (Construct::Footnote, line.len() - text.len())
     } else {
-        let code_obj = Box::new(knots_objects::CodeBlock {
-            contents: contents.to_owned(),
-            lang,
-        });
-        Ok((other, code_obj))
+        (Construct::Paragraph, 0)
+    };
+
+    let (offset, explanation) = diagnose_text(&line[prefix_len..]);
+
+    Failure {
+        offset: prefix_len + offset,
+        construct,
+        explanation,
+        resume: next_lines,
     }
 }
 
-/// Parses a maths block
-fn maths_block(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, contents) = delimited(tag("$$"), take_until("$$"), tag("$$"))(input)?;
-    let maths_obj = Box::new(knots_objects::MathsBlock {
-        contents: contents.to_owned(),
-    });
+/// Finds out why the objects in a quote or a box couldn't be parsed
+fn diagnose_prefixed<'a>(lines: &PrefixedLines<'a>) -> Failure<'a> {
+    let mut other = lines.body.as_str();
+    while let Ok((next, _)) = any_object(other) {
+        other = next;
+    }
 
-    Ok((other, maths_obj))
+    let (faulty, _) = multispace0::<_, ()>(other).unwrap_or((other, ""));
+    let failure = diagnose(faulty);
+    let body_offset = lines.body.len() - faulty.len() + failure.offset;
+
+    let construct = match failure.construct {
+        Construct::Paragraph => Construct::BlockQuote,
+        construct => construct,
+    };
+
+    Failure {
+        offset: lines.input_offset(body_offset),
+        construct,
+        explanation: failure.explanation,
+        resume: lines.rest,
+    }
 }
 
-/// Parses a list bullet
-fn list_item(input: &str, level: u8) -> IResult<&str, Vec<Box<dyn KnotsObject>>> {
-    // this item belongs to the list only if it has the right tabulation
-    let indent_level = alt((
-        count(tag(" "), (4 * level) as usize),
-        count(tag("\t"), level as usize),
-    ));
+/// Finds out why an admonition couldn't be parsed
+fn diagnose_admonition(input: &str) -> Failure<'_> {
+    let (line, next_lines) = split_line(input);
+
+    let (other, fence) = match admonition_start(input) {
+        Ok((other, (fence, _, _))) => (other, fence),
+        Err(_) => {
+            // either the kind or the title is faulty
+            let after_fence = line.trim_start_matches(':');
+            let fence_len = line.len() - after_fence.len();
+
+            let (offset, explanation) = match admonition_kind(after_fence) {
+                Ok((title, _)) => {
+                    let title_start = title.len() - title.trim_start().len();
+                    let (offset, explanation) = diagnose_text(title.trim_start());
+                    (line.len() - title.len() + title_start + offset, explanation)
+                }
+                Err(_) => (
+                    fence_len,
+                    "an admonition must start with its kind, like :::tip".to_owned(),
+                ),
+            };
+
+            return Failure {
+                offset,
+                construct: Construct::Admonition,
+                explanation,
+                resume: next_lines,
+            };
+        }
+    };
 
-    let (other, first_contents) = preceded(pair(indent_level, tag("-")), paragraph)(input)?;
+    // find the first faulty object in the contents
+    let mut contents = other;
+    loop {
+        if admonition_end(fence)(contents).is_ok() {
+            break;
+        }
 
-    // similarly, the next line belongs to this list item only if it has the right tabulation
-    let next_indent_level = alt((
-        count(tag(" "), 4 * (level + 1) as usize),
-        count(tag("\t"), (level + 1) as usize),
-    ));
+        match any_object(contents) {
+            Ok((next, _)) => contents = next,
+            Err(_) => break,
+        }
+    }
 
-    let (other, mut next_contents) = many0(alt((
-        |input| list(input, level + 1),
-        preceded(next_indent_level, paragraph),
-    )))(other)?;
+    let (faulty, _) = multispace0::<_, ()>(contents).unwrap_or((contents, ""));
+    if faulty.is_empty() {
+        return Failure {
+            offset: 0,
+            construct: Construct::Admonition,
+            explanation: format!(
+                "this admonition is never closed, add a {} line after its contents",
+                fence
+            ),
+            resume: "",
+        };
+    }
 
-    next_contents.insert(0, first_contents);
+    let failure = diagnose(faulty);
 
-    Ok((other, next_contents))
-}
+    // resume after the end of the admonition if there's one
+    let mut resume = failure.resume;
+    while !resume.is_empty() {
+        if let Ok((after_end, _)) = admonition_end(fence)(resume) {
+            resume = after_end;
+            break;
+        }
+        resume = split_line(resume).1;
+    }
 
-// Parses a list
-fn list(input: &str, level: u8) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, contents) = many1(|input| list_item(input, level))(input)?;
-    let list_obj = Box::new(knots_objects::List { contents });
-    Ok((other, list_obj))
+    Failure {
+        offset: input.len() - faulty.len() + failure.offset,
+        resume,
+        ..failure
+    }
 }
 
-/// Parses the delimiter after the table header e.g |---|---|
-fn table_delimiter(input: &str) -> IResult<&str, ()> {
-    let (other, _) = many1(pair(tag("|"), many1(tag("-"))))(input)?;
-    let (other, _) = pair(tag("|"), ws(eolf))(other)?;
-    Ok((other, ()))
+/// Finds out why a table couldn't be parsed
+fn diagnose_table(input: &str) -> Failure<'_> {
+    // a table spans over all the following lines with a pipe
+    let mut resume = input;
+    let mut lines = Vec::new();
+
+    loop {
+        let (line, next_lines) = split_line(resume);
+        if !line.contains('|') {
+            break;
+        }
+
+        lines.push((input.len() - resume.len(), line));
+        resume = next_lines;
+    }
+
+    let failure = |offset, explanation: &str| Failure {
+        offset,
+        construct: Construct::Table,
+        explanation: explanation.to_owned(),
+        resume,
+    };
+
+    let cells = |count| match count {
+        1 => "1 cell".to_owned(),
+        _ => format!("{} cells", count),
+    };
+
+    let columns = split_cells(lines[0].1).len();
+
+    for (i, &(line_offset, line)) in lines.iter().enumerate() {
+        if i == 1 {
+            match table_delimiter(line) {
+                Err(_) => {
+                    return failure(
+                        line_offset,
+                        "the header row must be followed by a delimiter row like |---|:---:|, where colons align the columns",
+                    )
+                }
+                Ok((_, alignments)) if alignments.len() != columns => {
+                    return failure(
+                        line_offset,
+                        &format!(
+                            "the delimiter row has {} but the header row has {}",
+                            cells(alignments.len()),
+                            cells(columns)
+                        ),
+                    )
+                }
+                Ok(_) => continue,
+            }
+        }
+
+        // look for a faulty cell
+        let row = split_cells(line);
+        for &(cell_offset, cell) in &row {
+            if !cell.trim().is_empty() {
+                let (offset, explanation) = diagnose_text(cell);
+                if offset != cell.len() {
+                    return failure(line_offset + cell_offset + offset, &explanation);
+                }
+            }
+        }
+
+        if row.len() != columns {
+            return failure(
+                line_offset,
+                &format!(
+                    "this row has {} but the header row has {}",
+                    cells(row.len()),
+                    cells(columns)
+                ),
+            );
+        }
+    }
+
+    match lines.len() {
+        1 => failure(
+            input.len() - resume.len(),
+            "the header row must be followed by a delimiter row like |---|:---:|, where colons align the columns",
+        ),
+        2 => failure(
+            input.len() - resume.len(),
+            "a table needs at least one row after its delimiter row",
+        ),
+        _ => failure(0, "this table couldn't be parsed"),
+    }
 }
 
-/// Parses a table row
-fn table_row(input: &str) -> IResult<&str, Vec<Vec<Box<dyn KnotsObject>>>> {
-    // match the field name
-    let (other, fields) = many1(delimited(
-        tag("|"),
-        ws(many0(any_text_modifier)),
-        peek(tag("|")),
-    ))(input)?;
+/// Splits a table row into its cells, with their offset in the row.
+/// Escaped pipes and pipes in inline code don't split the cells.
+fn split_cells(row: &str) -> Vec<(usize, &str)> {
+    let mut cells = Vec::new();
+    let mut start = 0;
+    let mut in_code = false;
+    let mut escaped = false;
+
+    for (i, c) in row.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if !in_code => escaped = true,
+            '`' => in_code = !in_code,
+            '|' if !in_code => {
+                cells.push((start, &row[start..i]));
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
 
-    // match the last closing pipe
-    let (other, _) = pair(tag("|"), eolf)(other)?;
-    Ok((other, fields))
+    cells.push((start, &row[start..]));
+
+    // the pipes around the row don't delimit cells
+    if row.trim_start().starts_with('|') {
+        cells.remove(0);
+    }
+
+    if cells.len() > 1 && cells.last().is_some_and(|(_, cell)| cell.trim().is_empty()) {
+        cells.pop();
+    }
+
+    cells
 }
 
-/// Parses a table
-fn table(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, header) = table_row(input)?;
-    let (other, _) = table_delimiter(other)?;
-    let (other, rows) = many1(table_row)(other)?;
+/// Finds out where and why some inline text couldn't be parsed
+fn diagnose_text(input: &str) -> (usize, String) {
+    let (faulty, _) = many0(any_text_modifier)(input).unwrap_or((input, Vec::new()));
+    let offset = input.len() - faulty.len();
 
-    let table_obj = Box::new(knots_objects::Table { header, rows });
-    Ok((other, table_obj))
+    let explanation = match faulty.chars().next() {
+        None | Some('\r') | Some('\n') => "this line couldn't be parsed".to_owned(),
+        Some('`') => {
+            "this inline code is never closed, add a ` or write \\` for a backtick".to_owned()
+        }
+        Some(']') => "this bracket doesn't end a link, write \\] for a literal `]`".to_owned(),
+        Some('@') => "a cross-reference must look like @ref(id)".to_owned(),
+        Some('{') => "an id must look like {#id}, with only letters, digits, `-`, `_`, `:` and `.`"
+            .to_owned(),
+        Some(c) => format!("unexpected character `{}`", c),
+    };
+
+    (offset, explanation)
 }
 
-/// Parses an image
-fn image(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    let (other, _) = tag("!")(input)?;
-    let (other, name) = delimited(tag("["), take_until("]"), tag("]"))(other)?;
-    let (other, link) = delimited(tag("("), take_until(")"), tag(")"))(other)?;
+/// Splits the first line of the input from the next ones
+fn split_line(input: &str) -> (&str, &str) {
+    match input.find('\n') {
+        Some(i) => (input[..i].trim_end_matches('\r'), &input[i + 1..]),
+        None => (input, ""),
+    }
+}
 
-    let img_obj = Box::new(knots_objects::Image {
-        alt: name.to_owned(),
-        link: link.to_owned(),
-    });
+/// Parses some text without any markup.
+/// An underscore inside a word like `snake_case` isn't an emphasis,
+/// a `#` is only special after a `{`, where it starts an id,
+/// `~`, `^` and `=` are only special when they start or end a style,
+/// and a `]` only when it ends the text of a link.
+/// The urls and email addresses starting a word are left to `autolink`.
+fn plain_text(input: &str) -> IResult<&str, &str> {
+    let mut previous = None;
+    let mut end = input.len();
+
+    for (i, c) in input.char_indices() {
+        let is_markup = match c {
+            '`' | '*' | '\r' | '\n' | '[' | '$' | '|' | '@' | '\\' => true,
+            // a style which can't start or end here is just text, like in "~5 minutes" or "a == b"
+            '~' | '^' | '=' => {
+                let rest = &input[i..];
+                let run = rest.len() - rest.trim_start_matches(c).len();
+
+                let starts = match c {
+                    '~' => strikethrough(rest).is_ok() || subscript(rest).is_ok(),
+                    '^' => superscript(rest).is_ok(),
+                    _ => highlight(rest).is_ok(),
+                };
+                // without a space before, `~~` and `==` may end a style around this text
+                let ends = run >= 2 && c != '^' && !previous.is_some_and(char::is_whitespace);
+
+                starts || ends
+            }
+            '_' => {
+                let after = input[i..].trim_start_matches('_').chars().next();
+                !(previous.is_some_and(|c: char| c.is_alphanumeric() || c == '_')
+                    && after.is_some_and(char::is_alphanumeric))
+            }
+            '{' => input[i..].starts_with("{#"),
+            ']' => input[i..].starts_with("](") || input[i..].starts_with("]["),
+            '<' => autolink(&input[i..]).is_ok(),
+            c if c.is_alphanumeric() => {
+                let starts_word =
+                    !previous.is_some_and(|c: char| c.is_alphanumeric() || ".+-_".contains(c));
+
+                starts_word && autolink(&input[i..]).is_ok()
+            }
+            _ => false,
+        };
+
+        if is_markup {
+            end = i;
+            break;
+        }
 
-    Ok((other, img_obj))
+        previous = Some(c);
+    }
+
+    match end {
+        0 => Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            ErrorKind::IsNot,
+        ))),
+        _ => Ok((&input[end..], &input[..end])),
+    }
 }
 
-/// Parses an object contained on one line
-fn any_object(input: &str) -> IResult<&str, Box<dyn KnotsObject>> {
-    delimited(
-        multispace0,
+/// Parses a backslash escape like `\*`, returning the escaped character.
+/// Any ASCII punctuation can be escaped, and the characters which are special in HTML
+/// are written as entities.
+fn escaped_char(input: &str) -> IResult<&str, &str> {
+    preceded(
+        tag("\\"),
         alt((
-            horizontal_ruler,
-            lvl3_title,
-            lvl2_title,
-            lvl1_title,
-            |input| list(input, 0),
-            table,
-            code_block,
-            maths_block,
-            image,
-            info_box,
-            warning_box,
-            error_box,
-            block_quote,
-            paragraph,
+            value("&lt;", tag("<")),
+            value("&gt;", tag(">")),
+            value("&amp;", tag("&")),
+            recognize(one_of("!\"#$%'()*+,-./:;=?@[\\]^_`{|}~")),
         )),
-        multispace0,
     )(input)
 }
+
+/// Parses a raw string
+fn basic(input: &str) -> IResult<&str, Node> {
+    let (other, (delimiter, contents)) = verify(
+        pair(
+            // the styles are tried first, so a delimiter here doesn't start one, like in "3 * 4"
+            // or "x==y", and neither does a `[` which isn't a link, like in "array[0]".
+            // A `|` outside of a table is just text too, like in "a | b".
+            // It's only text at the start, so the style around can still end with it
+            opt(recognize(one_of("*_~^=[|"))),
+            many0(alt((
+                // a dollar which doesn't start some maths, like in "$5 and $10"
+                recognize(preceded(not(inline_maths), tag("$"))),
+                recognize(pair(tag("@"), not(tag("ref(")))),
+                escaped_char,
+                tag("\\"),
+                plain_text,
+            ))),
+        ),
+        |(delimiter, contents): &(Option<&str>, Vec<&str>)| {
+            delimiter.is_some() || !contents.is_empty()
+        },
+    )(input)?;
+    let raw = Node::BasicText(knots_objects::BasicText {
+        contents: delimiter.into_iter().chain(contents).collect(),
+        span: span(input, other),
+    });
+
+    Ok((other, raw))
+}
+
+/// Matches the delimiter starting an emphasis with `*`, which can't be followed by a space
+/// so the one in "3 * 4" is just text
+fn emphasis_start(delimiter: &'static str) -> impl Fn(&str) -> IResult<&str, &str> {
+    move |input| terminated(tag(delimiter), peek(not(multispace1)))(input)
+}
+
+/// Parses an italic string using `*`
+fn italic1(input: &str) -> IResult<&str, Node> {
+    // a `*` ends the text, unless it starts a bold one like in `*a **b** c*`
+    let (other, contents) = delimited(
+        emphasis_start("*"),
+        many1(preceded(
+            not(preceded(not(bold1), tag("*"))),
+            any_text_modifier,
+        )),
+        tag("*"),
+    )(input)?;
+    let italic_obj = Node::Italic(knots_objects::Italic {
+        contents,
+        span: span(input, other),
+    });
+    Ok((other, italic_obj))
+}
+
+/// Parses an italic string using `_`
+fn italic2(input: &str) -> IResult<&str, Node> {
+    // a `_` ends the text, unless it starts a bold one like in `_a __b__ c_`
+    let (other, contents) = delimited(
+        tag("_"),
+        many1(preceded(
+            not(preceded(not(bold2), tag("_"))),
+            any_text_modifier,
+        )),
+        tag("_"),
+    )(input)?;
+    let italic_obj = Node::Italic(knots_objects::Italic {
+        contents,
+        span: span(input, other),
+    });
+    Ok((other, italic_obj))
+}
+
+/// Parses a bold string using `**`
+fn bold1(input: &str) -> IResult<&str, Node> {
+    let (other, contents) = delimited(
+        emphasis_start("**"),
+        many1(preceded(not(tag("**")), any_text_modifier)),
+        tag("**"),
+    )(input)?;
+    let bold_obj = Node::Bold(knots_objects::Bold {
+        contents,
+        span: span(input, other),
+    });
+    Ok((other, bold_obj))
+}
+
+/// Parses a bold string using `__`
+fn bold2(input: &str) -> IResult<&str, Node> {
+    let (other, contents) = delimited(
+        tag("__"),
+        many1(preceded(not(tag("__")), any_text_modifier)),
+        tag("__"),
+    )(input)?;
+    let bold_obj = Node::Bold(knots_objects::Bold {
+        contents,
+        span: span(input, other),
+    });
+    Ok((other, bold_obj))
+}
+
+/// Parses some text delimited by `delimiter`, like `~~deleted~~`.
+/// The text can't start with a space, so `a == b == c` isn't highlighted.
+fn delimited_text(delimiter: &'static str) -> impl Fn(&str) -> IResult<&str, Vec<Node>> {
+    move |input| {
+        delimited(
+            pair(tag(delimiter), peek(not(multispace1))),
+            many1(preceded(not(tag(delimiter)), any_text_modifier)),
+            tag(delimiter),
+        )(input)
+    }
+}
+
+/// Parses a strikethrough string using `~~`
+fn strikethrough(input: &str) -> IResult<&str, Node> {
+    let (other, contents) = delimited_text("~~")(input)?;
+    let strikethrough_obj = Node::Strikethrough(knots_objects::Strikethrough {
+        contents,
+        span: span(input, other),
+    });
+    Ok((other, strikethrough_obj))
+}
+
+/// Parses a highlighted string using `==`
+fn highlight(input: &str) -> IResult<&str, Node> {
+    let (other, contents) = delimited_text("==")(input)?;
+    let highlight_obj = Node::Highlight(knots_objects::Highlight {
+        contents,
+        span: span(input, other),
+    });
+    Ok((other, highlight_obj))
+}
+
+/// Parses the text of a subscript or a superscript delimited by `delimiter`,
+/// which can't contain spaces unless they're escaped, like `2` in `H~2~O`
+fn script_text(delimiter: &'static str) -> impl Fn(&str) -> IResult<&str, String> {
+    move |input| {
+        let (other, contents) = delimited(
+            tag(delimiter),
+            many1(alt((
+                escaped_char,
+                value(" ", tag("\\ ")),
+                is_not(" \t\r\n\\~^"),
+            ))),
+            tag(delimiter),
+        )(input)?;
+
+        Ok((other, contents.concat()))
+    }
+}
+
+/// Parses a subscript like `~2~`
+fn subscript(input: &str) -> IResult<&str, Node> {
+    let (other, contents) = script_text("~")(input)?;
+    let subscript_obj = Node::Subscript(knots_objects::Subscript {
+        contents,
+        span: span(input, other),
+    });
+    Ok((other, subscript_obj))
+}
+
+/// Parses a superscript like `^2^`
+fn superscript(input: &str) -> IResult<&str, Node> {
+    let (other, contents) = script_text("^")(input)?;
+    let superscript_obj = Node::Superscript(knots_objects::Superscript {
+        contents,
+        span: span(input, other),
+    });
+    Ok((other, superscript_obj))
+}
+
+/// Parses a key of the keyboard like `[[Ctrl]]`
+fn keyboard(input: &str) -> IResult<&str, Node> {
+    let (other, key) = delimited(tag("[["), is_not("]\r\n"), tag("]]"))(input)?;
+    let keyboard_obj = Node::Keyboard(knots_objects::Keyboard {
+        key: key.trim().to_owned(),
+        span: span(input, other),
+    });
+    Ok((other, keyboard_obj))
+}
+
+/// Parses the url of a link, which may be between `<>`.
+/// It can contain parentheses as long as they're balanced, like in `https://en.wikipedia.org/wiki/Rust_(fungus)`.
+fn link_destination(input: &str) -> IResult<&str, &str> {
+    alt((
+        delimited(tag("<"), is_not(">\r\n"), tag(">")),
+        recognize(many1(alt((
+            is_not(" \t\r\n()"),
+            recognize(delimited(tag("("), is_not(" \t\r\n()"), tag(")"))),
+        )))),
+    ))(input)
+}
+
+/// Parses the title of a link, like `"title"` or `'title'`
+fn link_title(input: &str) -> IResult<&str, &str> {
+    alt((
+        delimited(tag("\""), is_not("\"\r\n"), tag("\"")),
+        delimited(tag("'"), is_not("'\r\n"), tag("'")),
+    ))(input)
+}
+
+/// Parses the label of a link reference like `[knots]`
+fn link_label(input: &str) -> IResult<&str, &str> {
+    delimited(
+        tag("["),
+        verify(is_not("[]\r\n"), |label: &str| {
+            !label.starts_with('^') && !label.trim().is_empty()
+        }),
+        tag("]"),
+    )(input)
+}
+
+/// Normalizes the label of a link reference, which is case insensitive
+fn normalize_label(label: &str) -> String {
+    label
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Replaces the links in the text of another link by their text, since links can't be nested
+struct LinkFlattener;
+
+impl VisitorMut for LinkFlattener {
+    fn visit_nodes_mut(&mut self, nodes: &mut Vec<Node>) {
+        *nodes = std::mem::take(nodes)
+            .into_iter()
+            .flat_map(|node| match node {
+                Node::Link(link) => link.contents,
+                node => vec![node],
+            })
+            .collect();
+
+        for node in nodes.iter_mut() {
+            self.visit_node_mut(node);
+        }
+    }
+}
+
+/// Parses a link like `[text](url "title")`, or a reference link like `[text][label]`
+/// or `[text][]`, whose url is given by a definition somewhere else
+fn link(input: &str) -> IResult<&str, Node> {
+    let (after_text, mut contents) = delimited(
+        tag("["),
+        many1(preceded(not(tag("]")), any_text_modifier)),
+        tag("]"),
+    )(input)?;
+    let text = &input[1..input.len() - after_text.len() - 1];
+
+    LinkFlattener.visit_nodes_mut(&mut contents);
+
+    let (other, (link, title, reference)) = alt((
+        map(
+            delimited(
+                pair(tag("("), space0),
+                pair(opt(link_destination), opt(preceded(space1, link_title))),
+                pair(space0, tag(")")),
+            ),
+            |(link, title)| (link.unwrap_or_default(), title, None),
+        ),
+        map(link_label, |label| ("", None, Some(label))),
+        // `[text][]` uses the text as the label
+        map(tag("[]"), |_| ("", None, Some(text))),
+    ))(after_text)?;
+
+    let link_obj = Node::Link(knots_objects::Link {
+        contents,
+        link: link.to_owned(),
+        title: title.map(str::to_owned),
+        reference: reference.map(normalize_label),
+        span: span(input, other),
+    });
+
+    Ok((other, link_obj))
+}
+
+/// Parses the definition of a link reference like `[knots]: https://github.com "Knots"`
+fn link_definition(input: &str) -> IResult<&str, Node> {
+    let (other, label) = terminated(link_label, tag(":"))(input)?;
+    let (other, (link, title)) = delimited(
+        space0,
+        pair(link_destination, opt(preceded(space1, link_title))),
+        pair(space0, eolf),
+    )(other)?;
+
+    let definition_obj = Node::LinkDefinition(knots_objects::LinkDefinition {
+        label: normalize_label(label),
+        link: link.to_owned(),
+        title: title.map(str::to_owned),
+        span: span(input, other),
+    });
+
+    Ok((other, definition_obj))
+}
+
+/// Parses an url starting with `http://` or `https://`.
+/// The punctuation ending a sentence isn't part of it, and neither is an unbalanced parenthesis,
+/// like in "(see https://github.com)."
+fn url(input: &str) -> IResult<&str, &str> {
+    let (_, (scheme, mut url)) = pair(
+        alt((tag("https://"), tag("http://"))),
+        is_not(" \t\r\n<>\"`|[]"),
+    )(input)?;
+
+    loop {
+        url = url.trim_end_matches(&['.', ',', ':', ';', '!', '?', '\'', '*', '_', '~'][..]);
+
+        match url.strip_suffix(')') {
+            Some(shorter) if url.matches('(').count() < url.matches(')').count() => url = shorter,
+            _ => break,
+        }
+    }
+
+    if url.is_empty() {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            ErrorKind::Verify,
+        )));
+    }
+
+    let length = scheme.len() + url.len();
+    Ok((&input[length..], &input[..length]))
+}
+
+/// Parses an email address like `someone@example.com`
+fn email(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        take_while1(|c: char| c.is_alphanumeric() || ".+-_".contains(c)),
+        tag("@"),
+        verify(
+            recognize(separated_list1(
+                tag("."),
+                take_while1(|c: char| c.is_alphanumeric() || c == '-'),
+            )),
+            |domain: &str| domain.contains('.'),
+        ),
+    )))(input)
+}
+
+/// Parses an url or an email address written as is, or between `<>`
+fn autolink(input: &str) -> IResult<&str, Node> {
+    let (other, (text, link)) = alt((
+        map(alt((delimited(tag("<"), url, tag(">")), url)), |url| {
+            (url, url.to_owned())
+        }),
+        map(
+            alt((delimited(tag("<"), email, tag(">")), email)),
+            |email| (email, format!("mailto:{}", email)),
+        ),
+    ))(input)?;
+
+    let link_obj = Node::Link(knots_objects::Link {
+        contents: vec![Node::BasicText(knots_objects::BasicText {
+            contents: escape_html(text),
+            span: span(input, other),
+        })],
+        link,
+        title: None,
+        reference: None,
+        span: span(input, other),
+    });
+
+    Ok((other, link_obj))
+}
+
+/// Parses a footnote id like `[^id]`
+fn footnote_id(input: &str) -> IResult<&str, &str> {
+    delimited(tag("[^"), is_not("] \t\r\n"), tag("]"))(input)
+}
+
+/// Parses a reference to a footnote
+fn footnote_reference(input: &str) -> IResult<&str, Node> {
+    let (other, id) = footnote_id(input)?;
+    let reference_obj = Node::FootnoteReference(knots_objects::FootnoteReference {
+        id: id.to_owned(),
+        span: span(input, other),
+    });
+
+    Ok((other, reference_obj))
+}
+
+/// Parses a cross-reference like `@ref(intro)`
+fn cross_reference(input: &str) -> IResult<&str, Node> {
+    let (other, id) = delimited(tag("@ref("), take_while1(is_id_char), tag(")"))(input)?;
+    let reference_obj = Node::CrossReference(knots_objects::CrossReference {
+        id: id.to_owned(),
+        span: span(input, other),
+    });
+
+    Ok((other, reference_obj))
+}
+
+/// Parses inline code
+fn inline_code(input: &str) -> IResult<&str, Node> {
+    let (other, contents) = delimited(tag("`"), is_not("`"), tag("`"))(input)?;
+    let code_obj = Node::InlineCode(knots_objects::InlineCode {
+        contents: contents.to_owned(),
+        span: span(input, other),
+    });
+    Ok((other, code_obj))
+}
+
+/// Parses inline maths
+fn inline_maths(input: &str) -> IResult<&str, Node> {
+    // do not match sequences with the first character after the dollar sign being punctuation or space.
+    // This is to avoid false positives when using text like "this costs 300$ at this store".
+    // Similarly, the closing dollar sign can't follow a space or be followed by a digit,
+    // like in "$5 and $10".
+    let (other, contents) = delimited(
+        pair(tag("$"), peek(none_of(" ?!.,;"))),
+        verify(is_not("$"), |contents: &str| {
+            !contents.ends_with(char::is_whitespace)
+        }),
+        pair(tag("$"), not(digit1)),
+    )(input)?;
+    let maths_obj = Node::InlineMaths(knots_objects::InlineMaths {
+        contents: contents.to_owned(),
+        span: span(input, other),
+    });
+    Ok((other, maths_obj))
+}
+
+/// Parses as a bold, italic or raw string
+fn any_text_modifier(input: &str) -> IResult<&str, Node> {
+    alt((
+        footnote_reference,
+        cross_reference,
+        keyboard,
+        link,
+        bold1,
+        bold2,
+        italic1,
+        italic2,
+        strikethrough,
+        highlight,
+        subscript,
+        superscript,
+        inline_maths,
+        inline_code,
+        autolink,
+        basic,
+    ))(input)
+}
+
+/// Matches if we're at the end of a line or of the file
+fn eolf(input: &str) -> IResult<&str, &str> {
+    alt((line_ending, eof))(input)
+}
+
+/// Strips whitespaces
+fn ws<I, O, E>(input: impl Parser<I, O, E>) -> impl FnMut(I) -> IResult<I, O, E>
+where
+    I: InputTakeAtPosition,
+    <I as InputTakeAtPosition>::Item: AsChar + Clone,
+    E: NomParseError<I>,
+{
+    delimited(space0, input, space0)
+}
+
+/// Parses a Knots variable name
+fn variable(input: &str) -> IResult<&str, &str> {
+    preceded(tag("%"), alpha1)(input)
+}
+
+/// Parses a variable and its contents like %title Hello world
+/// to a pair (var_name, var_contents), in this case ("title", "Hello World")
+fn var_pair(input: &str) -> IResult<&str, (&str, &str)> {
+    separated_pair(variable, space1, terminated(not_line_ending, eolf))(input)
+}
+
+/// Parses a paragraph of text
+fn paragraph(input: &str) -> IResult<&str, Node> {
+    // a table which couldn't be parsed isn't text either, even if its pipes can be
+    let (line, next_lines) = split_line(input);
+    if looks_like_table(line, next_lines) {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            ErrorKind::Verify,
+        )));
+    }
+
+    // a code fence which isn't closed isn't text
+    let (other, contents) = delimited(not(code_fence), many1(any_text_modifier), eolf)(input)?;
+    let paragraph_obj = Node::Paragraph(knots_objects::Paragraph {
+        contents,
+        span: span(input, other),
+    });
+    Ok((other, paragraph_obj))
+}
+
+/// Consecutive lines starting with the same prefix, like the lines of a block quote
+struct PrefixedLines<'a> {
+    /// the lines without their prefix
+    body: String,
+    /// where each line starts, in the body and in the input
+    line_starts: Vec<(usize, usize)>,
+    /// the length of the input
+    input_len: usize,
+    /// what's left after the last line
+    rest: &'a str,
+}
+
+impl<'a> PrefixedLines<'a> {
+    /// Gathers the lines starting with `prefix`, or returns `None` if the first one doesn't.
+    /// The prefix may be indented and followed by a space, which are both stripped.
+    fn new(input: &'a str, prefix: &str) -> Option<Self> {
+        let mut body = String::new();
+        let mut line_starts = Vec::new();
+        let mut rest = input;
+
+        while let Some(line) = rest
+            .trim_start_matches(&[' ', '\t'][..])
+            .strip_prefix(prefix)
+        {
+            let line = line.strip_prefix(' ').unwrap_or(line);
+            let line_len = line.find('\n').map(|i| i + 1).unwrap_or_else(|| line.len());
+
+            line_starts.push((body.len(), input.len() - line.len()));
+            body.push_str(&line[..line_len]);
+            rest = &line[line_len..];
+        }
+
+        if line_starts.is_empty() {
+            return None;
+        }
+
+        Some(PrefixedLines {
+            body,
+            line_starts,
+            input_len: input.len(),
+            rest,
+        })
+    }
+
+    /// Converts an offset in the body to an offset in the input
+    fn input_offset(&self, body_offset: usize) -> usize {
+        let line = match self
+            .line_starts
+            .binary_search_by_key(&body_offset, |&(body_start, _)| body_start)
+        {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+
+        let (body_start, input_start) = self.line_starts[line];
+        input_start + body_offset - body_start
+    }
+
+    /// Converts the end of a range in the body to an offset in the input,
+    /// so that a range ending with a line doesn't include the prefix of the next one
+    fn input_end_offset(&self, body_offset: usize) -> usize {
+        match body_offset {
+            0 => self.input_offset(0),
+            _ => self.input_offset(body_offset - 1) + 1,
+        }
+    }
+}
+
+/// Moves the positions recorded while parsing the body to the input
+impl VisitorMut for PrefixedLines<'_> {
+    fn visit_nodes_mut(&mut self, nodes: &mut Vec<Node>) {
+        for node in nodes.iter_mut() {
+            let span = node.span_mut();
+            span.start = self.input_len - self.input_offset(self.body.len() - span.start);
+            span.end = self.input_len - self.input_end_offset(self.body.len() - span.end);
+
+            self.visit_node_mut(node);
+        }
+    }
+}
+
+/// Parses the lines starting with `prefix` as the objects they contain.
+/// If these objects are invalid, the parsing fails without trying other parsers,
+/// since these lines can't be anything else.
+fn prefixed_blocks(prefix: &'static str) -> impl Fn(&str) -> IResult<&str, Vec<Node>> {
+    move |input| {
+        let error = |kind| nom::error::Error::new(input, kind);
+
+        let mut lines = PrefixedLines::new(input, prefix)
+            .ok_or_else(|| nom::Err::Error(error(ErrorKind::Tag)))?;
+
+        let mut contents =
+            match terminated(many0(any_object), pair(multispace0, eof))(lines.body.as_str()) {
+                Ok((_, contents)) => contents,
+                Err(_) => return Err(nom::Err::Failure(error(ErrorKind::Many0))),
+            };
+
+        // an empty quote is just text
+        if contents.is_empty() {
+            return Err(nom::Err::Error(error(ErrorKind::Many1)));
+        }
+
+        lines.visit_nodes_mut(&mut contents);
+        Ok((lines.rest, contents))
+    }
+}
+
+/// Parses the start of a footnote definition like `[^id]: `
+fn footnote_definition_start(input: &str) -> IResult<&str, &str> {
+    terminated(footnote_id, pair(tag(":"), space0))(input)
+}
+
+/// Parses a footnote definition like `[^id]: Some text`,
+/// followed by its next paragraphs indented with 4 spaces or 1 tab
+fn footnote_definition(input: &str) -> IResult<&str, Node> {
+    let (other, id) = footnote_definition_start(input)?;
+    let (other, first_contents) = paragraph(other)?;
+    let (other, mut next_contents) =
+        many0(preceded(alt((tag("    "), tag("\t"))), paragraph))(other)?;
+
+    next_contents.insert(0, first_contents);
+
+    let definition_obj = Node::FootnoteDefinition(knots_objects::FootnoteDefinition {
+        id: id.to_owned(),
+        contents: next_contents,
+        span: span(input, other),
+    });
+
+    Ok((other, definition_obj))
+}
+
+/// Parses a Blockquote
+fn block_quote(input: &str) -> IResult<&str, Node> {
+    let (other, contents) = prefixed_blocks(">")(input)?;
+    let quote_obj = Node::BlockQuote(knots_objects::BlockQuote {
+        contents,
+        span: span(input, other),
+    });
+
+    Ok((other, quote_obj))
+}
+
+/// Parses the `?>`, `!>` and `x>` boxes,
+/// which are shorthands for the info, warning and error admonitions
+fn box_admonition(input: &str) -> IResult<&str, Node> {
+    let (_, (prefix, kind)) = alt((
+        value(("?>", "info"), tag("?>")),
+        value(("!>", "warning"), tag("!>")),
+        value(("x>", "error"), tag("x>")),
+    ))(input)?;
+
+    let (other, contents) = prefixed_blocks(prefix)(input)?;
+    let box_obj = Node::Admonition(knots_objects::Admonition {
+        kind: kind.to_owned(),
+        title: None,
+        contents,
+        span: span(input, other),
+    });
+
+    Ok((other, box_obj))
+}
+
+/// Parses the first line of an admonition like `:::tip Some title`,
+/// returning its fence, its kind and its title
+fn admonition_start(input: &str) -> IResult<&str, (&str, &str, Option<Vec<Node>>)> {
+    let (other, fence) = verify(is_a(":"), |fence: &str| fence.len() >= 3)(input)?;
+    let (other, kind) = admonition_kind(other)?;
+    let (other, title) = terminated(
+        opt(preceded(space1, many1(any_text_modifier))),
+        preceded(space0, eolf),
+    )(other)?;
+
+    Ok((other, (fence, kind, title)))
+}
+
+/// Parses the kind of an admonition, like `tip`
+fn admonition_kind(input: &str) -> IResult<&str, &str> {
+    recognize(many1(alt((alphanumeric1, tag("-")))))(input)
+}
+
+/// Parses the line closing an admonition
+fn admonition_end<'a>(fence: &'a str) -> impl Fn(&'a str) -> IResult<&'a str, ()> {
+    move |input| {
+        let (other, _) = delimited(pair(multispace0, tag(fence)), space0, eolf)(input)?;
+        Ok((other, ()))
+    }
+}
+
+/// Parses an admonition like
+/// ```text
+/// :::tip Some title
+/// Some contents
+/// :::
+/// ```
+/// The contents can be any object, including another admonition.
+fn admonition(input: &str) -> IResult<&str, Node> {
+    let (other, (fence, kind, title)) = admonition_start(input)?;
+
+    // once the first line is there, this can't be anything else than an admonition
+    let (other, contents) = cut(terminated(
+        many0(preceded(not(admonition_end(fence)), any_object)),
+        admonition_end(fence),
+    ))(other)?;
+
+    let admonition_obj = Node::Admonition(knots_objects::Admonition {
+        kind: kind.to_owned(),
+        title,
+        contents,
+        span: span(input, other),
+    });
+
+    Ok((other, admonition_obj))
+}
+
+/// Parses an horizontal ruler
+fn horizontal_ruler(input: &str) -> IResult<&str, Node> {
+    let (other, _) = delimited(
+        alt((tag("***"), tag("---"), tag("___"))),
+        many0(is_a("*_-")),
+        eolf,
+    )(input)?;
+    let hr_obj = Node::HorizontalRule(knots_objects::HorizontalRule {
+        span: span(input, other),
+    });
+    Ok((other, hr_obj))
+}
+
+/// Parses a title, from level 1 with `#` to level 6 with `######`
+fn title(input: &str) -> IResult<&str, Node> {
+    let (other, hashes) = verify(is_a("#"), |hashes: &str| hashes.len() <= 6)(input)?;
+    let (other, contents) = terminated(ws(not_line_ending), eolf)(other)?;
+    let (contents, id) = split_explicit_id(contents);
+    let title_obj = Node::Title(knots_objects::Title {
+        contents: contents.to_owned(),
+        level: hashes.len() as u8,
+        id: id.map(str::to_owned),
+        span: span(input, other),
+    });
+    Ok((other, title_obj))
+}
+
+/// Checks if a character is allowed in an explicit id
+fn is_id_char(c: char) -> bool {
+    c.is_alphanumeric() || "-_:.".contains(c)
+}
+
+/// Parses an explicit id like `{#intro}`
+fn explicit_id(input: &str) -> IResult<&str, &str> {
+    delimited(tag("{#"), take_while1(is_id_char), tag("}"))(input)
+}
+
+/// Splits the explicit id at the end of some text like `Introduction {#intro}`
+fn split_explicit_id(contents: &str) -> (&str, Option<&str>) {
+    let start = match contents.rfind("{#") {
+        Some(start) => start,
+        None => return (contents, None),
+    };
+
+    match terminated(explicit_id, eof)(&contents[start..]) {
+        Ok((_, id)) => (contents[..start].trim_end(), Some(id)),
+        Err(_) => (contents, None),
+    }
+}
+
+/// Is this character allowed in a language annotation, like in `c++` or `objective-c` ?
+fn is_language_char(c: char) -> bool {
+    c.is_alphanumeric() || "+-#._".contains(c)
+}
+
+/// An attribute given after the language of a code block
+#[derive(Clone)]
+enum CodeAttribute<'a> {
+    Title(&'a str),
+    LineNumbers,
+    Highlight(Vec<(usize, usize)>),
+    Start(usize),
+}
+
+/// Parses a line number
+fn line_number(input: &str) -> IResult<&str, usize> {
+    map_res(digit1, str::parse)(input)
+}
+
+/// Parses a range of lines like `3-5`, or a single line
+fn line_range(input: &str) -> IResult<&str, (usize, usize)> {
+    let (other, (first, last)) = verify(
+        pair(line_number, opt(preceded(tag("-"), line_number))),
+        |&(first, last)| first <= last.unwrap_or(first),
+    )(input)?;
+
+    Ok((other, (first, last.unwrap_or(first))))
+}
+
+/// Parses an attribute of a code block like `title="main.rs"`, `linenos`, `start=10` or `{3-5,9}`
+fn code_attribute(input: &str) -> IResult<&str, CodeAttribute<'_>> {
+    alt((
+        map(
+            delimited(tag("title=\""), is_not("\"\r\n"), tag("\"")),
+            CodeAttribute::Title,
+        ),
+        value(
+            CodeAttribute::LineNumbers,
+            terminated(tag("linenos"), not(alphanumeric1)),
+        ),
+        map(
+            delimited(
+                tag("{"),
+                separated_list1(tag(","), ws(line_range)),
+                tag("}"),
+            ),
+            CodeAttribute::Highlight,
+        ),
+        map(preceded(tag("start="), line_number), CodeAttribute::Start),
+    ))(input)
+}
+
+/// Parses the fence opening or closing a code block,
+/// which is made of at least three backticks or tildes
+fn code_fence(input: &str) -> IResult<&str, &str> {
+    verify(alt((is_a("`"), is_a("~"))), |fence: &str| fence.len() >= 3)(input)
+}
+
+/// Parses the line closing a code block opened with `fence`, indented with `indent`.
+/// The closing fence must be made of the same character, and be at least as long,
+/// so a code block can contain shorter fences.
+fn code_fence_end<'a>(fence: &'a str, indent: &'a str) -> impl Fn(&'a str) -> IResult<&'a str, ()> {
+    move |input| {
+        let (other, _) = delimited(
+            pair(tag(indent), many_m_n(0, 3, tag(" "))),
+            verify(code_fence, |closing: &str| {
+                closing.starts_with(&fence[..1]) && closing.len() >= fence.len()
+            }),
+            pair(space0, eolf),
+        )(input)?;
+
+        Ok((other, ()))
+    }
+}
+
+/// Parses a code block
+fn code_block(input: &str) -> IResult<&str, Node> {
+    indented_code_block("")(input)
+}
+
+/// Parses a code block whose lines are indented with `indent`, like in a list item.
+/// This indentation is removed from the code.
+fn indented_code_block<'a>(indent: &'a str) -> impl Fn(&'a str) -> IResult<&'a str, Node> {
+    move |input| {
+        let (other, fence) = code_fence(input)?;
+
+        // try to read the language annotation and the attributes if they exist
+        let (other, lang) = opt(take_while1(is_language_char))(other)?;
+        let (other, attributes) = many0(preceded(space1, code_attribute))(other)?;
+        let (other, _) = pair(space0, line_ending)(other)?;
+        let (other, (lines, _)) = many_till(
+            terminated(not_line_ending, line_ending),
+            code_fence_end(fence, indent),
+        )(other)?;
+
+        let contents = lines
+            .into_iter()
+            .map(|line| format!("{}\n", line.strip_prefix(indent).unwrap_or(line)))
+            .collect();
+
+        Ok((other, code_object(input, other, contents, lang, attributes)))
+    }
+}
+
+/// Makes the object of a code block from its contents, its language and its attributes
+fn code_object(
+    input: &str,
+    other: &str,
+    contents: String,
+    lang: Option<&str>,
+    attributes: Vec<CodeAttribute>,
+) -> Node {
+    let lang = lang.unwrap_or_default().to_lowercase();
+
+    // if the language annotation is mermaid, render as a mermaid diagram
+    if lang == "mermaid" {
+        Node::Mermaid(knots_objects::Mermaid {
+            contents,
+            span: span(input, other),
+        })
+
+    // else it's a prism code block
+    } else {
+        let mut code = knots_objects::CodeBlock {
+            contents,
+            lang,
+            title: None,
+            line_numbers: false,
+            start: 1,
+            highlighted: Vec::new(),
+            span: span(input, other),
+        };
+
+        for attribute in attributes {
+            match attribute {
+                CodeAttribute::Title(title) => code.title = Some(title.to_owned()),
+                CodeAttribute::LineNumbers => code.line_numbers = true,
+                CodeAttribute::Highlight(ranges) => code.highlighted.extend(ranges),
+                // giving the first line number implies numbering the lines
+                CodeAttribute::Start(start) => {
+                    code.line_numbers = true;
+                    code.start = start;
+                }
+            }
+        }
+
+        Node::CodeBlock(code)
+    }
+}
+
+/// Parses a maths block
+fn maths_block(input: &str) -> IResult<&str, Node> {
+    let (other, contents) = delimited(tag("$$"), take_until("$$"), tag("$$"))(input)?;
+
+    // a label after the block numbers it like `$$ {#energy}`
+    let (other, id) = opt(preceded(space0, explicit_id))(other)?;
+
+    let maths_obj = Node::MathsBlock(knots_objects::MathsBlock {
+        contents: contents.to_owned(),
+        id: id.map(str::to_owned),
+        span: span(input, other),
+    });
+
+    Ok((other, maths_obj))
+}
+
+/// The marker starting a list item
+#[derive(Clone, Copy)]
+enum ListMarker {
+    /// a `-` bullet
+    Bullet,
+    /// a number followed by a `.` or a `)` delimiter
+    Ordered { number: usize, delimiter: char },
+}
+
+impl ListMarker {
+    /// Checks if two items with these markers belong to the same list
+    fn same_list(self, other: ListMarker) -> bool {
+        match (self, other) {
+            (ListMarker::Bullet, ListMarker::Bullet) => true,
+            (
+                ListMarker::Ordered { delimiter: a, .. },
+                ListMarker::Ordered { delimiter: b, .. },
+            ) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Parses a list marker like `-`, `1.` or `1)`
+fn list_marker(input: &str) -> IResult<&str, ListMarker> {
+    alt((
+        map(tag("-"), |_| ListMarker::Bullet),
+        // a space is required after a number so "3.14 is pi" isn't a list
+        map(
+            terminated(
+                pair(map_res(digit1, str::parse), one_of(".)")),
+                peek(space1),
+            ),
+            |(number, delimiter)| ListMarker::Ordered { number, delimiter },
+        ),
+    ))(input)
+}
+
+/// Parses a task checkbox like `[ ]` or `[x]`, returning whether it's checked
+fn task_checkbox(input: &str) -> IResult<&str, bool> {
+    terminated(
+        alt((
+            map(tag("[ ]"), |_| false),
+            map(alt((tag("[x]"), tag("[X]"))), |_| true),
+        )),
+        peek(alt((space1, eolf))),
+    )(input)
+}
+
+/// Parses a list item
+fn list_item(input: &str, level: u8) -> IResult<&str, (ListMarker, ListItem)> {
+    // this item belongs to the list only if it has the right tabulation
+    let indent_level = alt((
+        count(tag(" "), (4 * level) as usize),
+        count(tag("\t"), level as usize),
+    ));
+
+    let (other, marker) = preceded(indent_level, list_marker)(input)?;
+    let (other, checked) = opt(preceded(space1, task_checkbox))(other)?;
+    let (other, first_contents) = match checked {
+        // a task may have no text yet, like `- [ ]`
+        Some(_) => alt((map(paragraph, Some), value(None, pair(space0, eolf))))(other)?,
+        None => map(paragraph, Some)(other)?,
+    };
+
+    // similarly, the next line belongs to this list item only if it has the right tabulation
+    let next_indent_level = |input| {
+        recognize(alt((
+            count(tag(" "), 4 * (level + 1) as usize),
+            count(tag("\t"), (level + 1) as usize),
+        )))(input)
+    };
+
+    // code blocks may be separated from the text by blank lines
+    let code_block = |input| {
+        let (other, indent) = preceded(many0(pair(space0, line_ending)), next_indent_level)(input)?;
+        indented_code_block(indent)(other)
+    };
+
+    let (other, mut next_contents) = many0(alt((
+        |input| list(input, level + 1),
+        code_block,
+        preceded(next_indent_level, paragraph),
+    )))(other)?;
+
+    if let Some(first_contents) = first_contents {
+        next_contents.insert(0, first_contents);
+    }
+
+    let item = ListItem {
+        contents: next_contents,
+        checked,
+    };
+
+    Ok((other, (marker, item)))
+}
+
+// Parses a list
+fn list(input: &str, level: u8) -> IResult<&str, Node> {
+    let (other, (marker, first_item)) = list_item(input, level)?;
+
+    // the next items must have the same kind of marker
+    let (other, next_items) = many0(verify(
+        |input| list_item(input, level),
+        |(next_marker, _)| marker.same_list(*next_marker),
+    ))(other)?;
+
+    let start = match marker {
+        ListMarker::Bullet => None,
+        ListMarker::Ordered { number, .. } => Some(number),
+    };
+
+    let contents = std::iter::once(first_item)
+        .chain(next_items.into_iter().map(|(_, item)| item))
+        .collect();
+
+    let list_obj = Node::List(knots_objects::List {
+        contents,
+        start,
+        span: span(input, other),
+    });
+    Ok((other, list_obj))
+}
+
+/// Parses a cell of the delimiter row like `:---:`,
+/// where the colons give the alignment of its column
+fn delimiter_cell(input: &str) -> IResult<&str, Option<Alignment>> {
+    let (other, (left, _, right)) = ws(tuple((opt(tag(":")), is_a("-"), opt(tag(":")))))(input)?;
+
+    let alignment = match (left, right) {
+        (Some(_), Some(_)) => Some(Alignment::Center),
+        (Some(_), None) => Some(Alignment::Left),
+        (None, Some(_)) => Some(Alignment::Right),
+        (None, None) => None,
+    };
+
+    Ok((other, alignment))
+}
+
+/// Parses the delimiter after the table header e.g |---|:---:|,
+/// returning the alignment of each column
+fn table_delimiter(input: &str) -> IResult<&str, Vec<Option<Alignment>>> {
+    let (other, leading) = opt(preceded(space0, tag("|")))(input)?;
+    let (other, first) = delimiter_cell(other)?;
+    let (other, next) = many0(preceded(tag("|"), delimiter_cell))(other)?;
+    let (other, trailing) = opt(tag("|"))(other)?;
+    let (other, _) = ws(eolf)(other)?;
+
+    // without any pipe, it would be a horizontal ruler
+    if leading.is_none() && next.is_empty() && trailing.is_none() {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            ErrorKind::Verify,
+        )));
+    }
+
+    Ok((other, std::iter::once(first).chain(next).collect()))
+}
+
+/// Does this line start a table ?
+/// The pipes around the rows are optional, but a table always has a delimiter row.
+fn looks_like_table(line: &str, next_lines: &str) -> bool {
+    line.trim_start().starts_with('|')
+        || (line.contains('|') && table_delimiter(next_lines).is_ok())
+}
+
+/// Parses the text of a table cell, which ends at the next pipe
+fn table_cell_text(input: &str) -> IResult<&str, Node> {
+    preceded(not(tag("|")), any_text_modifier)(input)
+}
+
+/// Parses a table row, with or without pipes around it
+fn table_row(input: &str) -> IResult<&str, Vec<Vec<Node>>> {
+    let (other, leading) = opt(preceded(space0, tag("|")))(input)?;
+    let (other, first) = ws(many0(table_cell_text))(other)?;
+    let (other, mut next) = many0(preceded(tag("|"), ws(many0(table_cell_text))))(other)?;
+    let (other, _) = eolf(other)?;
+
+    // a row is only told apart from a paragraph by its pipes
+    if leading.is_none() && next.is_empty() {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            ErrorKind::Verify,
+        )));
+    }
+
+    // the closing pipe leaves an empty cell behind it
+    if next.last().is_some_and(Vec::is_empty) {
+        next.pop();
+    }
+
+    Ok((other, std::iter::once(first).chain(next).collect()))
+}
+
+/// The caption of a table or an image, with its explicit id
+type Caption<'a> = (Option<Vec<Node>>, Option<&'a str>);
+
+/// Parses the line after a table or an image like `Table: Some caption {#id}`,
+/// where both the caption and the id are optional
+fn caption(label: &'static str) -> impl Fn(&str) -> IResult<&str, Caption> {
+    move |input| {
+        // the caption is parsed in place, so the positions of its nodes are right
+        let (other, (mut caption, id)) = delimited(
+            pair(tag(label), space0),
+            pair(many0(any_text_modifier), opt(explicit_id)),
+            pair(space0, eolf),
+        )(input)?;
+
+        // the spaces before the id aren't part of the caption
+        if let Some(Node::BasicText(text)) = caption.last_mut() {
+            text.contents.truncate(text.contents.trim_end().len());
+
+            if text.contents.is_empty() {
+                caption.pop();
+            }
+        }
+
+        let caption = if caption.is_empty() {
+            None
+        } else {
+            Some(caption)
+        };
+
+        Ok((other, (caption, id)))
+    }
+}
+
+/// Parses a table
+fn table(input: &str) -> IResult<&str, Node> {
+    let (other, header) = table_row(input)?;
+    let (other, alignments) = table_delimiter(other)?;
+    let (other, rows) = many1(table_row)(other)?;
+
+    // every row must have a cell for each column
+    if alignments.len() != header.len() || rows.iter().any(|row| row.len() != header.len()) {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            ErrorKind::Verify,
+        )));
+    }
+
+    let (other, caption) = opt(caption("Table:"))(other)?;
+    let (caption, id) = caption.unwrap_or((None, None));
+
+    let table_obj = Node::Table(knots_objects::Table {
+        header,
+        rows,
+        alignments,
+        caption,
+        id: id.map(str::to_owned),
+        span: span(input, other),
+    });
+    Ok((other, table_obj))
+}
+
+/// Parses an image
+fn image(input: &str) -> IResult<&str, Node> {
+    let (other, _) = tag("!")(input)?;
+    let (other, name) = delimited(tag("["), take_until("]"), tag("]"))(other)?;
+    let (other, link) = delimited(tag("("), take_until(")"), tag(")"))(other)?;
+    let (other, attributes) = opt(preceded(peek(tag("{")), cut(image_attributes)))(other)?;
+    let (other, caption) = opt(preceded(pair(space0, line_ending), caption("Figure:")))(other)?;
+    let (caption, id) = caption.unwrap_or((None, None));
+
+    let mut img = knots_objects::Image {
+        alt: name.to_owned(),
+        link: link.to_owned(),
+        width: None,
+        height: None,
+        align: None,
+        caption,
+        id: id.map(str::to_owned),
+        span: span(input, other),
+    };
+
+    for attribute in attributes.unwrap_or_default() {
+        match attribute {
+            ImageAttribute::Width(width) => img.width = Some(width.to_owned()),
+            ImageAttribute::Height(height) => img.height = Some(height.to_owned()),
+            ImageAttribute::Align(align) => img.align = Some(align),
+        }
+    }
+
+    let img_obj = Node::Image(img);
+
+    Ok((other, img_obj))
+}
+
+/// An attribute given after an image, like `width=300`
+enum ImageAttribute<'a> {
+    Width(&'a str),
+    Height(&'a str),
+    Align(Alignment),
+}
+
+/// Parses a length like `300`, `300px` or `50%`
+fn length(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        digit1,
+        opt(alt((tag("px"), tag("%"), tag("em"), tag("rem")))),
+    ))(input)
+}
+
+/// Parses the attributes of an image like `{width=300 align=left}`
+fn image_attributes(input: &str) -> IResult<&str, Vec<ImageAttribute<'_>>> {
+    let attribute = alt((
+        map(preceded(tag("width="), length), ImageAttribute::Width),
+        map(preceded(tag("height="), length), ImageAttribute::Height),
+        map(
+            preceded(
+                tag("align="),
+                alt((
+                    value(Alignment::Left, tag("left")),
+                    value(Alignment::Right, tag("right")),
+                    value(Alignment::Center, tag("center")),
+                )),
+            ),
+            ImageAttribute::Align,
+        ),
+    ));
+
+    delimited(
+        pair(tag("{"), space0),
+        separated_list1(space1, attribute),
+        pair(space0, tag("}")),
+    )(input)
+}
+
+/// Parses a block object
+fn any_object(input: &str) -> IResult<&str, Node> {
+    delimited(
+        multispace0,
+        alt((
+            horizontal_ruler,
+            title,
+            |input| list(input, 0),
+            table,
+            code_block,
+            maths_block,
+            image,
+            admonition,
+            box_admonition,
+            block_quote,
+            footnote_definition,
+            link_definition,
+            paragraph,
+        )),
+        multispace0,
+    )(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a document which must be invalid, returning its diagnostics
+    fn diagnostics(input: &str) -> Vec<Diagnostic> {
+        match parse_str(input, "test.md") {
+            Err(ParseError::Syntax(diagnostics)) => diagnostics,
+            other => panic!("expected syntax errors, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn reports_syntax_errors_with_their_position() {
+        let diagnostics = diagnostics("# Intro\n\nSome `code\n\n| a | b |\n|---|\n| c | d |\n");
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].construct, Construct::Paragraph);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (3, 6));
+        assert_eq!(diagnostics[0].excerpt, "Some `code");
+        assert_eq!(diagnostics[1].construct, Construct::Table);
+        assert_eq!((diagnostics[1].line, diagnostics[1].column), (6, 1));
+    }
+
+    #[test]
+    fn warns_about_unknown_metadata() {
+        let parse_result = parse_str("%title Notes\n%autor Jane\n\nText\n", "test.md").unwrap();
+        let diagnostics = &parse_result.diagnostics;
+
+        assert_eq!(parse_result.document_title, "Notes");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].severity,
+            crate::diagnostics::Severity::Warning
+        );
+        assert_eq!(diagnostics[0].construct, Construct::Metadata);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (2, 1));
+        assert_eq!(diagnostics[0].excerpt, "%autor Jane");
+        assert!(diagnostics[0].explanation.contains("%autor"));
+    }
+
+    #[test]
+    fn reports_unclosed_code_fences() {
+        let diagnostics = diagnostics("Text\n\n```python\nprint(1)\n");
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].construct, Construct::CodeFence);
+        assert_eq!(diagnostics[0].line, 3);
+        assert!(diagnostics[0].explanation.contains("add a ``` line"));
+    }
+
+    #[test]
+    fn exports_the_document_tree_to_json() {
+        let input =
+            "%title Notes\n%author Ann\n%license MIT\n\n# Intro\n\n```rust\nfn main() {}\n```\n";
+        let parse_result = parse_str(input, "notes.md").unwrap();
+        let json = serde_json::to_value(&parse_result).unwrap();
+
+        assert_eq!(json["document_title"], "Notes");
+        assert_eq!(json["document_authors"], serde_json::json!(["Ann"]));
+        assert_eq!(json["document_license"], "MIT");
+
+        let contents = &json["root_object"]["contents"];
+        assert_eq!(contents[0]["type"], "Title");
+        assert_eq!(contents[0]["span"]["line"], 5);
+        assert_eq!(contents[1]["type"], "CodeBlock");
+        assert_eq!(contents[1]["lang"], "rust");
+        assert_eq!(contents[1]["contents"], "fn main() {}\n");
+        assert_eq!(contents[1]["span"]["line"], 7);
+        assert_eq!(contents[1]["span"]["column"], 1);
+        assert_eq!(
+            contents[1]["span"]["start"],
+            input.find("```").unwrap() as u64
+        );
+    }
+
+    /// Parses a valid document, returning its top-level nodes
+    fn nodes(input: &str) -> Vec<Node> {
+        parse_str(input, "test.md").unwrap().root_object.contents
+    }
+
+    #[test]
+    fn parses_ordered_lists_from_their_first_number() {
+        let nodes = nodes("3. Three\n4. Four\n\n1) One\n2. Two\n\n3.14 is pi\n");
+
+        let lists: Vec<_> = nodes
+            .iter()
+            .filter_map(|node| match node {
+                Node::List(list) => Some((list.start, list.contents.len())),
+                _ => None,
+            })
+            .collect();
+
+        // a new delimiter starts a new list
+        assert_eq!(lists, [(Some(3), 2), (Some(1), 1), (Some(2), 1)]);
+        assert!(matches!(nodes.last(), Some(Node::Paragraph(_))));
+    }
+
+    #[test]
+    fn parses_task_checkboxes() {
+        let nodes =
+            nodes("- [x] Done\n- [ ] To do\n- [link](https://github.com)\n- [X] Also done\n");
+
+        let list = match &nodes[0] {
+            Node::List(list) => list,
+            other => panic!("expected a list, got {:?}", other),
+        };
+        let checked: Vec<_> = list.contents.iter().map(|item| item.checked).collect();
+
+        assert_eq!(checked, [Some(true), Some(false), None, Some(true)]);
+    }
+
+    #[test]
+    fn parses_task_checkboxes_without_text() {
+        let nodes = nodes("- [ ]\n- [x]\n- [ ] To do\n\nAfter\n");
+
+        let list = match &nodes[0] {
+            Node::List(list) => list,
+            other => panic!("expected a list, got {:?}", other),
+        };
+        let checked: Vec<_> = list.contents.iter().map(|item| item.checked).collect();
+
+        assert_eq!(checked, [Some(false), Some(true), Some(false)]);
+        assert!(list.contents[0].contents.is_empty());
+        assert!(list.contents[1].contents.is_empty());
+        assert_eq!(list.contents[2].contents.len(), 1);
+        assert!(matches!(nodes[1], Node::Paragraph(_)));
+    }
+
+    #[test]
+    fn parses_blocks_inside_quotes_and_boxes() {
+        let nodes = nodes(
+            "> First\n>\n> Second\n> > Nested\n\n!> Run:\n!> ```sh\n!> rm -rf build\n!> ```\n!> - then build\n",
+        );
+        assert_eq!(nodes.len(), 2);
+
+        let quote = match &nodes[0] {
+            Node::BlockQuote(quote) => quote,
+            other => panic!("expected a quote, got {:?}", other),
+        };
+        assert!(matches!(
+            quote.contents.as_slice(),
+            [Node::Paragraph(_), Node::Paragraph(_), Node::BlockQuote(_)]
+        ));
+
+        let warning = match &nodes[1] {
+            Node::Admonition(admonition) => admonition,
+            other => panic!("expected a box, got {:?}", other),
+        };
+        assert_eq!(warning.kind, "warning");
+        assert!(matches!(
+            warning.contents.as_slice(),
+            [Node::Paragraph(_), Node::CodeBlock(code), Node::List(_)] if code.contents == "rm -rf build\n"
+        ));
+    }
+
+    #[test]
+    fn reports_errors_inside_quotes_at_their_position() {
+        let diagnostics = diagnostics("> Fine\n>\n>  Some `code\n");
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].construct, Construct::BlockQuote);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (3, 9));
+    }
+
+    #[test]
+    fn parses_titled_and_nested_admonitions() {
+        let nodes = nodes("::::note A *nice* title\nText\n\n:::tip\nNested\n:::\n::::\n");
+
+        let note = match &nodes[..] {
+            [Node::Admonition(admonition)] => admonition,
+            other => panic!("expected an admonition, got {:?}", other),
+        };
+        assert_eq!(note.kind, "note");
+        assert_eq!(note.title.as_ref().map(Vec::len), Some(3));
+        assert!(matches!(
+            &note.contents[..],
+            [Node::Paragraph(_), Node::Admonition(tip)] if tip.kind == "tip" && tip.title.is_none()
+        ));
+    }
+
+    #[test]
+    fn reports_unclosed_admonitions() {
+        let diagnostics = diagnostics("Intro\n\n::::tip\nText\n:::\n");
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].construct, Construct::Admonition);
+        assert_eq!(diagnostics[0].line, 3);
+        assert!(diagnostics[0].explanation.contains("add a :::: line"));
+    }
+
+    #[test]
+    fn parses_multi_line_footnotes() {
+        let nodes = nodes("Text[^note].\n\n[^note]: First line\n    second line\n\nAfter\n");
+
+        assert!(matches!(
+            &nodes[1],
+            Node::FootnoteDefinition(definition) if definition.id == "note" && definition.contents.len() == 2
+        ));
+        assert!(matches!(&nodes[2], Node::Paragraph(_)));
+    }
+
+    #[test]
+    fn reports_undefined_and_duplicate_footnotes() {
+        let diagnostics = diagnostics("Text[^a] and[^b].\n\n[^a]: One\n[^a]: Two\n");
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].construct, Construct::Footnote);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (1, 13));
+        assert!(diagnostics[0].explanation.contains("[^b] is never defined"));
+        assert_eq!(diagnostics[1].line, 4);
+        assert!(diagnostics[1]
+            .explanation
+            .contains("[^a] is already defined"));
+    }
+
+    #[test]
+    fn parses_explicit_title_ids() {
+        let nodes = nodes("## Intro {#intro}\n\n### Not an {#id here}\n");
+
+        let titles: Vec<_> = nodes
+            .iter()
+            .filter_map(|node| match node {
+                Node::Title(title) => {
+                    Some((title.level, title.contents.as_str(), title.id.as_deref()))
+                }
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(
+            titles,
+            [(2, "Intro", Some("intro")), (3, "Not an {#id here}", None)]
+        );
+    }
+
+    #[test]
+    fn reports_duplicate_ids() {
+        let diagnostics = diagnostics("# Intro {#intro}\n\n# Again {#intro}\n");
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].construct, Construct::Id);
+        assert_eq!(diagnostics[0].line, 3);
+    }
+
+    #[test]
+    fn reports_ids_taking_the_form_of_generated_ones() {
+        for id in &[
+            "fn-1",
+            "fnref-1",
+            "fnref-1-2",
+            "table-1",
+            "figure-3",
+            "maths0",
+            "footnotes",
+            "license",
+            "doctitle",
+        ] {
+            let diagnostics = diagnostics(&format!("# Intro {{#{}}}\n", id));
+
+            assert_eq!(diagnostics.len(), 1, "{} isn't reserved", id);
+            assert_eq!(diagnostics[0].construct, Construct::Id);
+            assert!(diagnostics[0].explanation.contains("is reserved"));
+        }
+
+        // the ids of the tables, figures and equations are checked too
+        let diagnostics =
+            diagnostics("| a |\n|---|\n| 1 |\nTable: Numbers {#table-1}\n\n$$\nx\n$$ {#maths1}\n");
+        assert_eq!(diagnostics.len(), 2);
+
+        // the other ids starting like them are fine
+        let input =
+            "# A {#fn-intro}\n\n# B {#table-of-contents}\n\n# C {#maths}\n\n# D {#fnref-1-2-3}\n";
+        assert!(parse_str(input, "test.md").is_ok());
+    }
+
+    #[test]
+    fn reports_references_to_missing_ids() {
+        let diagnostics = diagnostics("# Intro {#intro}\n\nSee @ref(intro) and\n@ref(outro).\n");
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].construct, Construct::CrossReference);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (4, 1));
+        assert!(diagnostics[0]
+            .explanation
+            .contains("nothing has the id outro"));
+    }
+
+    #[test]
+    fn parses_equation_labels() {
+        let nodes = nodes("$$\nE = mc^2\n$$ {#energy}\n\n$$x$$\n");
+
+        let labels: Vec<_> = nodes
+            .iter()
+            .filter_map(|node| match node {
+                Node::MathsBlock(maths) => Some((maths.contents.as_str(), maths.id.as_deref())),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(labels, [("\nE = mc^2\n", Some("energy")), ("x", None)]);
+    }
+
+    #[test]
+    fn parses_image_attributes_and_captions() {
+        let nodes =
+            nodes("![A cat](cat.png){width=300 height=50% align=right}\nFigure: A *cat* {#cat}\n");
+
+        let image = match &nodes[0] {
+            Node::Image(image) => image,
+            other => panic!("expected an image, got {:?}", other),
+        };
+        assert_eq!(image.width.as_deref(), Some("300"));
+        assert_eq!(image.height.as_deref(), Some("50%"));
+        assert_eq!(image.align, Some(Alignment::Right));
+        assert_eq!(image.caption.as_ref().map(Vec::len), Some(2));
+        assert_eq!(image.id.as_deref(), Some("cat"));
+    }
+
+    #[test]
+    fn reports_invalid_image_attributes() {
+        let diagnostics = diagnostics("![A cat](cat.png){size=300}\n");
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].construct, Construct::Image);
+        assert_eq!(diagnostics[0].column, 18);
+    }
+
+    #[test]
+    fn parses_table_alignments_and_pipes_in_cells() {
+        let nodes = nodes("Name | Pipe | Note\n:--|--:|:-:\n`a|b` | c \\| d | |\n");
+
+        let table = match &nodes[0] {
+            Node::Table(table) => table,
+            other => panic!("expected a table, got {:?}", other),
+        };
+        assert_eq!(
+            table.alignments,
+            [
+                Some(Alignment::Left),
+                Some(Alignment::Right),
+                Some(Alignment::Center)
+            ]
+        );
+        assert_eq!(table.rows.len(), 1);
+
+        let row = &table.rows[0];
+        assert_eq!(row.len(), 3);
+        assert!(matches!(&row[0][0], Node::InlineCode(code) if code.contents == "a|b"));
+        assert!(matches!(&row[1][..], [Node::BasicText(text)] if text.contents.trim() == "c | d"));
+        assert!(row[2].is_empty());
+    }
+
+    #[test]
+    fn reports_table_rows_with_missing_cells() {
+        let diagnostics = diagnostics("a | b\n--|--\n1 | 2\n3 | 4 | 5\n");
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].construct, Construct::Table);
+        assert_eq!(diagnostics[0].line, 4);
+    }
+
+    #[test]
+    fn parses_code_block_attributes() {
+        let nodes = nodes("```rust title=\"src/main.rs\" start=10 {11,13-14}\nfn main() {}\n```\n\n```py linenos\npass\n```\n");
+
+        let blocks: Vec<_> = nodes
+            .iter()
+            .filter_map(|node| match node {
+                Node::CodeBlock(code) => Some(code),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(blocks[0].lang, "rust");
+        assert_eq!(blocks[0].title.as_deref(), Some("src/main.rs"));
+        assert!(blocks[0].line_numbers);
+        assert_eq!(blocks[0].start, 10);
+        assert_eq!(blocks[0].highlighted, [(11, 11), (13, 14)]);
+
+        assert_eq!(blocks[1].lang, "py");
+        assert!(blocks[1].line_numbers);
+        assert_eq!(blocks[1].start, 1);
+        assert!(blocks[1].highlighted.is_empty());
+    }
+
+    #[test]
+    fn reports_invalid_code_block_attributes() {
+        let diagnostics = diagnostics("```rust linenos lines=3\nfn main() {}\n```\n");
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].construct, Construct::CodeFence);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (1, 17));
+        assert!(diagnostics[0]
+            .explanation
+            .contains("`lines=3` isn't a code block attribute"));
+    }
+
+    #[test]
+    fn closes_code_blocks_on_a_matching_fence() {
+        let nodes = nodes("~~~md\n```rust\nfn main() {}\n```\n~~~\n\n````\n```\n`````\n");
+
+        let contents: Vec<_> = nodes
+            .iter()
+            .filter_map(|node| match node {
+                Node::CodeBlock(code) => Some(code.contents.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(contents, ["```rust\nfn main() {}\n```\n", "```\n"]);
+    }
+
+    #[test]
+    fn parses_code_blocks_in_list_items() {
+        let nodes = nodes("- Build it:\n\n    ```sh\n    make\n      all\n    ```\n- Done\n");
+
+        let list = match &nodes[..] {
+            [Node::List(list)] => list,
+            other => panic!("expected a list, got {:?}", other),
+        };
+        assert_eq!(list.contents.len(), 2);
+        assert!(matches!(
+            &list.contents[0].contents[..],
+            [Node::Paragraph(_), Node::CodeBlock(code)] if code.contents == "make\n  all\n"
+        ));
+    }
+
+    #[test]
+    fn parses_backslash_escapes() {
+        assert_eq!(
+            inline_text(r"\*stars\*, \`tick\`, \<b\>, a \\ and \q"),
+            r"*stars*, `tick`, &lt;b&gt;, a \ and \q"
+        );
+        assert_eq!(
+            inline_text("A #hashtag, snake_case_names, $5 and $10"),
+            "A #hashtag, snake_case_names, $5 and $10"
+        );
+        assert!(matches!(
+            &inline("$x^2$ costs $5")[..],
+            [Node::InlineMaths(_), Node::BasicText(_)]
+        ));
+    }
+
+    /// Collects the url and the title of the links
+    #[derive(Default)]
+    struct LinkCollector {
+        links: Vec<(String, Option<String>)>,
+    }
+
+    impl Visitor for LinkCollector {
+        fn visit_link(&mut self, link: &Link) {
+            self.links.push((link.link.clone(), link.title.clone()));
+        }
+    }
+
+    fn links(input: &str) -> Vec<(String, Option<String>)> {
+        let parse_result = parse_str(input, "test.md").unwrap();
+        let mut collector = LinkCollector::default();
+        collector.visit_root(&parse_result.root_object);
+        collector.links
+    }
+
+    #[test]
+    fn resolves_reference_links() {
+        let links = links(
+            "[Inline](https://a.org 'A title'), [Ref][The  Crate] and [the crate][].\n\n[the crate]: <https://crates.io/knots> \"Knots\"\n",
+        );
+
+        let knots = (
+            "https://crates.io/knots".to_owned(),
+            Some("Knots".to_owned()),
+        );
+        assert_eq!(
+            links,
+            [
+                ("https://a.org".to_owned(), Some("A title".to_owned())),
+                knots.clone(),
+                knots
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_autolinks() {
+        let links = links("See https://en.wikipedia.org/wiki/Rust_(fungus). (or <https://github.com>, me@example.com)\n");
+
+        let urls: Vec<_> = links.iter().map(|(link, _)| link.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://en.wikipedia.org/wiki/Rust_(fungus)",
+                "https://github.com",
+                "mailto:me@example.com"
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_undefined_and_duplicate_links() {
+        let diagnostics =
+            diagnostics("See [this][nope].\n\n[a]: https://a.org\n[A]: https://b.org\n");
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].construct, Construct::Link);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (1, 5));
+        assert!(diagnostics[0]
+            .explanation
+            .contains("[nope] is never defined"));
+        assert_eq!(diagnostics[1].construct, Construct::Link);
+        assert_eq!(diagnostics[1].line, 4);
+        assert!(diagnostics[1]
+            .explanation
+            .contains("[a] is already defined"));
+    }
+
+    #[test]
+    fn reports_invalid_numberings() {
+        let diagnostics = diagnostics("%title Notes\n%numbering roman,nope\n\n# Intro\n");
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].construct, Construct::Metadata);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (2, 1));
+        assert!(diagnostics[0]
+            .explanation
+            .contains("unknown numbering nope"));
+    }
+
+    #[test]
+    fn parses_table_captions_with_multibyte_characters() {
+        let input = "| a | b |\n|---|---|\n| 1 | 2 |\nTable: Café *x* {#t}\n\ndéjà vu été\n";
+        let parse_result = parse_str(input, "test.md").unwrap();
+
+        let table = match &parse_result.root_object.contents[0] {
+            Node::Table(table) => table,
+            other => panic!("expected a table, got {:?}", other),
+        };
+        assert_eq!(table.id.as_deref(), Some("t"));
+
+        let caption = table.caption.as_ref().unwrap();
+        match &caption[0] {
+            Node::BasicText(text) => assert_eq!(text.contents, "Café "),
+            other => panic!("expected some text, got {:?}", other),
+        }
+
+        let italic = caption[1].span();
+        assert_eq!((italic.line, italic.column), (4, 13));
+        assert_eq!(&input[italic.start..italic.end], "*x*");
+    }
+
+    #[test]
+    fn reports_references_in_figure_captions_where_they_are() {
+        let diagnostics = diagnostics(
+            "![A photo](photo.png)\nFigure: Sée @ref(nope)\n\nSome text\n\nMore text\n",
+        );
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].construct, Construct::CrossReference);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (2, 13));
+    }
+
+    /// Parses a one-line document, returning the nodes of its paragraph
+    fn inline(input: &str) -> Vec<Node> {
+        let mut parse_result = parse_str(input, "test.md").unwrap();
+
+        match parse_result.root_object.contents.pop() {
+            Some(Node::Paragraph(paragraph)) => paragraph.contents,
+            other => panic!("expected a paragraph, got {:?}", other),
+        }
+    }
+
+    /// Parses a one-line document which must only be text, returning it
+    fn inline_text(input: &str) -> String {
+        match &inline(input)[..] {
+            [Node::BasicText(text)] => text.contents.clone(),
+            other => panic!("expected some text, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn keeps_lone_emphasis_delimiters_as_text() {
+        assert_eq!(inline_text("3 * 4 = 12"), "3 * 4 = 12");
+        assert_eq!(inline_text("a*b"), "a*b");
+        assert_eq!(inline_text("*not closed"), "*not closed");
+        assert_eq!(inline_text("a _ b"), "a _ b");
+        assert_eq!(inline_text("snake_case"), "snake_case");
+        assert_eq!(inline_text("2 ** 8"), "2 ** 8");
+    }
+
+    #[test]
+    fn parses_nested_emphasis() {
+        match &inline("*a **b** c*")[..] {
+            [Node::Italic(italic)] => {
+                assert!(matches!(&italic.contents[1], Node::Bold(_)));
+                assert!(
+                    matches!(&italic.contents[2], Node::BasicText(text) if text.contents == " c")
+                );
+            }
+            other => panic!("expected an italic text, got {:?}", other),
+        }
+
+        match &inline("**a *b* c**")[..] {
+            [Node::Bold(bold)] => assert!(matches!(&bold.contents[1], Node::Italic(_))),
+            other => panic!("expected a bold text, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn keeps_lone_style_delimiters_as_text() {
+        assert_eq!(inline_text("if x==y then"), "if x==y then");
+        assert_eq!(inline_text("approx~~ value"), "approx~~ value");
+        assert_eq!(inline_text("a == b == c"), "a == b == c");
+        assert_eq!(inline_text("~5 minutes"), "~5 minutes");
+        assert_eq!(inline_text("2^10 and x^"), "2^10 and x^");
+        assert_eq!(inline_text("~~not closed"), "~~not closed");
+    }
+
+    #[test]
+    fn parses_inline_styles() {
+        match &inline("~~old~~ ==new== H~2~O x^2^")[..] {
+            [Node::Strikethrough(_), _, Node::Highlight(_), _, Node::Subscript(subscript), _, Node::Superscript(superscript)] =>
+            {
+                assert_eq!(subscript.contents, "2");
+                assert_eq!(superscript.contents, "2");
+            }
+            other => panic!("expected styled text, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn keeps_lone_brackets_and_pipes_as_text() {
+        assert_eq!(inline_text("see [1] for more"), "see [1] for more");
+        assert_eq!(inline_text("array[0] is first"), "array[0] is first");
+        assert_eq!(inline_text("a | b"), "a | b");
+        assert_eq!(inline_text("[x || y"), "[x || y");
+        assert!(matches!(
+            &inline("see [1] and [the docs](https://github.com)")[..],
+            [Node::BasicText(_), Node::Link(_)]
+        ));
+
+        // the pipes still split the cells of the tables
+        let nodes = nodes("a | b\n---|---\n[1] | c\n");
+        let table = match &nodes[..] {
+            [Node::Table(table)] => table,
+            other => panic!("expected a table, got {:?}", other),
+        };
+        assert_eq!(table.rows[0].len(), 2);
+    }
+}
@@ -434,6 +434,10 @@ lazy_static! {
         "xquery": "markup"
     };
     static ref LANG_ALIASES: HashMap<&'static str, Vec<&'static str>> = hashmap! {
+        "c++": "cpp",
+        "c#": "csharp",
+        "f#": "fsharp",
+        "objective-c": "objectivec",
         "html": "markup",
         "xml": "markup",
         "svg": "markup",
@@ -513,17 +517,18 @@ lazy_static! {
     };
 }
 
+/// Finds the prism name of a language, which may be given by one of its aliases
+pub fn canonical_language(lang: &str) -> &str {
+    LANG_ALIASES.get(lang).map(|vec| vec[0]).unwrap_or(lang)
+}
+
 /// Retreives the prism plugins needed for these languages
 pub fn find_plugins(langs: &[String]) -> Vec<&'static str> {
     let mut already_found = HashSet::new();
     let mut plugins = Vec::new();
 
     for lang in langs {
-        // find the abbreviation if there is one
-        let lang = LANG_ALIASES
-            .get(lang.as_str())
-            .map(|vec| vec[0])
-            .unwrap_or(lang);
+        let lang = canonical_language(lang);
 
         // get the dependency plugins and add the main plugin
         plugins.extend(
@@ -0,0 +1,525 @@
+/// The SVG elements kept when an image is included in the page.
+/// None of them can run scripts, load other resources, style the page or hold HTML,
+/// so `<script>`, `<style>`, `<foreignObject>`, `<image>`, `<a>` or the animations are removed.
+const ALLOWED_ELEMENTS: &[&str] = &[
+    "svg",
+    "g",
+    "defs",
+    "symbol",
+    "use",
+    "title",
+    "desc",
+    "path",
+    "rect",
+    "circle",
+    "ellipse",
+    "line",
+    "polyline",
+    "polygon",
+    "text",
+    "tspan",
+    "textPath",
+    "linearGradient",
+    "radialGradient",
+    "stop",
+    "clipPath",
+    "mask",
+    "pattern",
+    "marker",
+    "filter",
+    "feBlend",
+    "feColorMatrix",
+    "feComponentTransfer",
+    "feComposite",
+    "feConvolveMatrix",
+    "feDiffuseLighting",
+    "feDisplacementMap",
+    "feDistantLight",
+    "feDropShadow",
+    "feFlood",
+    "feFuncA",
+    "feFuncB",
+    "feFuncG",
+    "feFuncR",
+    "feGaussianBlur",
+    "feMerge",
+    "feMergeNode",
+    "feMorphology",
+    "feOffset",
+    "fePointLight",
+    "feSpecularLighting",
+    "feSpotLight",
+    "feTile",
+    "feTurbulence",
+];
+
+/// The attributes kept on the allowed elements, which leaves out the `on*` event handlers
+const ALLOWED_ATTRIBUTES: &[&str] = &[
+    "alignment-baseline",
+    "amplitude",
+    "aria-hidden",
+    "aria-label",
+    "azimuth",
+    "baseFrequency",
+    "baseline-shift",
+    "bias",
+    "class",
+    "clip-path",
+    "clip-rule",
+    "clipPathUnits",
+    "color",
+    "color-interpolation-filters",
+    "cx",
+    "cy",
+    "d",
+    "diffuseConstant",
+    "display",
+    "divisor",
+    "dominant-baseline",
+    "dx",
+    "dy",
+    "edgeMode",
+    "elevation",
+    "exponent",
+    "fill",
+    "fill-opacity",
+    "fill-rule",
+    "filter",
+    "filterUnits",
+    "flood-color",
+    "flood-opacity",
+    "font-family",
+    "font-size",
+    "font-style",
+    "font-weight",
+    "fr",
+    "fx",
+    "fy",
+    "gradientTransform",
+    "gradientUnits",
+    "height",
+    "href",
+    "id",
+    "in",
+    "in2",
+    "intercept",
+    "k1",
+    "k2",
+    "k3",
+    "k4",
+    "kernelMatrix",
+    "lengthAdjust",
+    "letter-spacing",
+    "lighting-color",
+    "limitingConeAngle",
+    "marker-end",
+    "marker-mid",
+    "marker-start",
+    "markerHeight",
+    "markerUnits",
+    "markerWidth",
+    "mask",
+    "maskContentUnits",
+    "maskUnits",
+    "mode",
+    "numOctaves",
+    "offset",
+    "opacity",
+    "operator",
+    "order",
+    "orient",
+    "overflow",
+    "paint-order",
+    "pathLength",
+    "patternContentUnits",
+    "patternTransform",
+    "patternUnits",
+    "points",
+    "pointsAtX",
+    "pointsAtY",
+    "pointsAtZ",
+    "preserveAlpha",
+    "preserveAspectRatio",
+    "primitiveUnits",
+    "r",
+    "radius",
+    "refX",
+    "refY",
+    "result",
+    "role",
+    "rotate",
+    "rx",
+    "ry",
+    "scale",
+    "seed",
+    "shape-rendering",
+    "slope",
+    "specularConstant",
+    "specularExponent",
+    "spreadMethod",
+    "startOffset",
+    "stdDeviation",
+    "stitchTiles",
+    "stop-color",
+    "stop-opacity",
+    "stroke",
+    "stroke-dasharray",
+    "stroke-dashoffset",
+    "stroke-linecap",
+    "stroke-linejoin",
+    "stroke-miterlimit",
+    "stroke-opacity",
+    "stroke-width",
+    "surfaceScale",
+    "tableValues",
+    "targetX",
+    "targetY",
+    "text-anchor",
+    "text-decoration",
+    "textLength",
+    "transform",
+    "type",
+    "values",
+    "vector-effect",
+    "version",
+    "viewBox",
+    "visibility",
+    "width",
+    "word-spacing",
+    "x",
+    "x1",
+    "x2",
+    "xChannelSelector",
+    "xlink:href",
+    "xml:lang",
+    "xml:space",
+    "xmlns",
+    "xmlns:xlink",
+    "y",
+    "y1",
+    "y2",
+    "yChannelSelector",
+    "z",
+];
+
+/// An SVG image with only the elements and attributes which are safe in the page
+#[derive(Debug)]
+pub struct SanitizedSvg {
+    /// the markup from the svg tag, without the XML declaration, the doctype and the comments
+    pub markup: String,
+    /// was anything removed ? The image may then look different than in an `<img>`
+    pub removed: bool,
+}
+
+/// Keeps the allowed elements and attributes of an SVG image,
+/// or returns `None` if it isn't well-formed enough to be sure of what it contains
+pub fn sanitize_svg(data: &[u8]) -> Option<SanitizedSvg> {
+    let text = std::str::from_utf8(data).ok()?;
+
+    let mut sanitizer = Sanitizer {
+        rest: &text[text.find("<svg")?..],
+        markup: String::new(),
+        removed: false,
+    };
+    sanitizer.sanitize()?;
+
+    Some(SanitizedSvg {
+        markup: sanitizer.markup,
+        removed: sanitizer.removed,
+    })
+}
+
+/// Walks through the tags of an SVG image, writing the allowed ones back
+struct Sanitizer<'a> {
+    /// what's left to read
+    rest: &'a str,
+    /// the allowed markup read so far
+    markup: String,
+    /// was anything removed so far ?
+    removed: bool,
+}
+
+impl<'a> Sanitizer<'a> {
+    fn sanitize(&mut self) -> Option<()> {
+        // the open elements, and whether they're kept
+        let mut open: Vec<(&str, bool)> = Vec::new();
+
+        loop {
+            let kept = !matches!(open.last(), Some((_, false)));
+
+            // the text until the next tag
+            let text_end = self.rest.find('<')?;
+            let text = &self.rest[..text_end];
+            decode_entities(text)?;
+            if kept {
+                self.markup += text;
+            }
+            self.rest = &self.rest[text_end..];
+
+            if self.rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.rest.starts_with("<![CDATA[") {
+                self.skip_past("]]>")?;
+                self.removed |= kept;
+            } else if self.rest.starts_with("<?") {
+                self.skip_past("?>")?;
+                self.removed |= kept;
+            } else if self.rest.starts_with("<!") {
+                // a doctype can only come before the svg tag
+                return None;
+            } else if let Some(rest) = self.rest.strip_prefix("</") {
+                let end = rest.find('>')?;
+                let name = rest[..end].trim_end();
+
+                match open.pop() {
+                    Some((open_name, open_kept)) if open_name == name => {
+                        if open_kept {
+                            self.markup += &format!("</{}>", name);
+                        }
+                    }
+                    _ => return None,
+                }
+
+                self.rest = &rest[end + 1..];
+            } else {
+                let (name, self_closing) = self.start_tag(open.is_empty(), kept)?;
+
+                if !self_closing {
+                    open.push((name, kept && ALLOWED_ELEMENTS.contains(&name)));
+                }
+            }
+
+            // everything after the svg tag is left out
+            if open.is_empty() {
+                return Some(());
+            }
+        }
+    }
+
+    /// Reads a start tag, writing it back if it's allowed,
+    /// and returns its name and whether it's self-closing
+    fn start_tag(&mut self, is_root: bool, parent_kept: bool) -> Option<(&'a str, bool)> {
+        let rest = &self.rest[1..];
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            .unwrap_or(rest.len());
+        let name = &rest[..name_end];
+
+        if !is_valid_name(name) || (is_root && name != "svg") {
+            return None;
+        }
+
+        let kept = parent_kept && ALLOWED_ELEMENTS.contains(&name);
+        self.removed |= parent_kept && !kept;
+
+        if kept {
+            self.markup += &format!("<{}", name);
+        }
+
+        self.rest = &rest[name_end..];
+
+        loop {
+            self.rest = self.rest.trim_start();
+
+            if let Some(rest) = self.rest.strip_prefix("/>") {
+                self.rest = rest;
+                if kept {
+                    self.markup += "/>";
+                }
+                return Some((name, true));
+            }
+
+            if let Some(rest) = self.rest.strip_prefix('>') {
+                self.rest = rest;
+                if kept {
+                    self.markup += ">";
+                }
+                return Some((name, false));
+            }
+
+            let (attribute, value) = self.attribute()?;
+
+            if !kept {
+                continue;
+            }
+
+            if is_allowed_attribute(attribute, &decode_entities(value)?) {
+                self.markup += &format!(" {}=\"{}\"", attribute, value.replace('"', "&quot;"));
+            } else {
+                self.removed = true;
+            }
+        }
+    }
+
+    /// Reads an attribute, returning its name and its value as written
+    fn attribute(&mut self) -> Option<(&'a str, &'a str)> {
+        let name_end = self.rest.find(|c: char| c.is_whitespace() || c == '=')?;
+        let name = &self.rest[..name_end];
+
+        if !is_valid_name(name) {
+            return None;
+        }
+
+        let rest = self.rest[name_end..].trim_start().strip_prefix('=')?;
+        let rest = rest.trim_start();
+
+        let quote = rest.chars().next().filter(|&c| c == '"' || c == '\'')?;
+        let value_end = rest[1..].find(quote)? + 1;
+        let value = &rest[1..value_end];
+
+        if value.contains('<') {
+            return None;
+        }
+
+        self.rest = &rest[value_end + 1..];
+        Some((name, value))
+    }
+
+    /// Skips everything until after this end marker
+    fn skip_past(&mut self, end: &str) -> Option<()> {
+        let index = self.rest.find(end)?;
+        self.rest = &self.rest[index + end.len()..];
+        Some(())
+    }
+}
+
+/// Is this a name of element or attribute we can read back without ambiguity ?
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == ':' || c == '-' || c == '_' || c == '.')
+}
+
+/// Is this attribute safe to keep ?
+/// Links must stay inside the image, so do the urls of the paints, filters and markers.
+fn is_allowed_attribute(name: &str, value: &str) -> bool {
+    if !ALLOWED_ATTRIBUTES.contains(&name) {
+        return false;
+    }
+
+    let lowercase = value.to_lowercase();
+
+    if (name == "href" || name == "xlink:href") && !lowercase.trim_start().starts_with('#') {
+        return false;
+    }
+
+    // CSS escapes could hide another url
+    if lowercase.contains('\\') {
+        return false;
+    }
+
+    lowercase.match_indices("url(").all(|(index, _)| {
+        lowercase[index + 4..]
+            .trim_start_matches(|c: char| c.is_whitespace() || c == '"' || c == '\'')
+            .starts_with('#')
+    })
+}
+
+/// Decodes the character references of some text,
+/// or returns `None` if one of them isn't one of the XML ones
+fn decode_entities(text: &str) -> Option<String> {
+    let mut decoded = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded += &rest[..start];
+        rest = &rest[start + 1..];
+
+        let end = rest.find(';')?;
+        let entity = &rest[..end];
+        rest = &rest[end + 1..];
+
+        let character = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = if let Some(hex) = entity.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok()?
+                } else {
+                    entity.strip_prefix('#')?.parse().ok()?
+                };
+                std::char::from_u32(code)?
+            }
+        };
+
+        decoded.push(character);
+    }
+
+    decoded += rest;
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitize(svg: &str) -> SanitizedSvg {
+        sanitize_svg(svg.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn keeps_safe_images() {
+        let svg = "<?xml version=\"1.0\"?>\n<!-- a circle -->\n<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox='0 0 10 10'><defs><linearGradient id=\"g\"><stop offset=\"0\" stop-color=\"red\"/></linearGradient></defs><circle cx=\"5\" cy=\"5\" r=\"4\" fill=\"url(#g)\"/><use href=\"#g\"/><text>1 &lt; 2</text></svg>\n";
+        let sanitized = sanitize(svg);
+
+        assert!(!sanitized.removed);
+        assert_eq!(
+            sanitized.markup,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 10 10\"><defs><linearGradient id=\"g\"><stop offset=\"0\" stop-color=\"red\"/></linearGradient></defs><circle cx=\"5\" cy=\"5\" r=\"4\" fill=\"url(#g)\"/><use href=\"#g\"/><text>1 &lt; 2</text></svg>"
+        );
+    }
+
+    #[test]
+    fn removes_unsafe_elements() {
+        for element in &[
+            "<script>alert(1)</script>",
+            "<SCRIPT>alert(1)</SCRIPT>",
+            "<style>body { display: none; }</style>",
+            "<foreignObject><p>Hi</p></foreignObject>",
+            "<image href=\"https://example.com/track.png\"/>",
+            "<a href=\"#x\"><rect/></a>",
+            "<set attributeName=\"href\" to=\"javascript:alert(1)\"/>",
+        ] {
+            let sanitized = sanitize(&format!("<svg><rect/>{}</svg>", element));
+
+            assert!(sanitized.removed, "{} wasn't removed", element);
+            assert_eq!(sanitized.markup, "<svg><rect/></svg>");
+        }
+    }
+
+    #[test]
+    fn removes_unsafe_attributes() {
+        for attribute in &[
+            "onload=\"alert(1)\"",
+            "ONLOAD=\"alert(1)\"",
+            "href=\"https://example.com/sprite.svg#icon\"",
+            "xlink:href=\"javascript:alert(1)\"",
+            "fill=\"url(https://example.com/paint.svg#p)\"",
+            "fill=\"url&#40;https://example.com/paint.svg#p)\"",
+            "style=\"fill: red\"",
+        ] {
+            let sanitized = sanitize(&format!("<svg><use {}/></svg>", attribute));
+
+            assert!(sanitized.removed, "{} wasn't removed", attribute);
+            assert_eq!(sanitized.markup, "<svg><use/></svg>");
+        }
+    }
+
+    #[test]
+    fn refuses_what_it_cannot_read() {
+        for svg in &[
+            "<svg><rect></svg>",
+            "<svg><rect width=10/></svg>",
+            "<svg><text>&custom;</text></svg>",
+            "<svg><!DOCTYPE svg></svg>",
+            "<svgfoo></svgfoo>",
+            "<svg>",
+        ] {
+            assert!(sanitize_svg(svg.as_bytes()).is_none(), "{} was read", svg);
+        }
+    }
+}
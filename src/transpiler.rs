@@ -1,13 +1,255 @@
-use super::builder::Builder;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use serde::Serialize;
+
+use super::builder::{count_footnote_reference, Builder};
+use super::diagnostics::Diagnostic;
+use super::images::ImageOptimisation;
+use super::knots_objects::{
+    FootnoteDefinition, FootnoteReference, Image, MathsBlock, Table, Title,
+};
 use super::parser::ParseResult;
+use super::utils::{get_alpha_numeral, get_roman_numeral};
+use super::visitor::Visitor;
+
+/// A kind of admonition, in addition to the built-in ones
+#[derive(Debug, Clone)]
+pub struct AdmonitionKind {
+    /// the name used in the document, like `tip` in `:::tip`.
+    /// It must only contain letters, digits and dashes.
+    pub name: String,
+    /// the svg icon shown next to the contents
+    pub icon: String,
+    /// the css background colour of the box, like `#80e27e` or `teal`.
+    /// Any other value is ignored.
+    pub colour: String,
+}
+
+impl AdmonitionKind {
+    /// Is this a colour which can be written in the css of the page,
+    /// a hexadecimal one like `#80e27e` or a name like `teal` ?
+    pub fn is_valid_colour(colour: &str) -> bool {
+        match colour.strip_prefix('#') {
+            Some(hex) => {
+                [3, 4, 6, 8].contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit())
+            }
+            None => !colour.is_empty() && colour.chars().all(|c| c.is_ascii_alphabetic()),
+        }
+    }
+}
+
+/// How the titles of a level are numbered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Numbering {
+    /// I, II, III...
+    Roman,
+    /// 1, 2, 3...
+    Arabic,
+    /// A, B, C...
+    Alpha,
+    /// 1.1, 1.2, 1.2.1... with the numbers of the parent titles
+    Dotted,
+    /// no number
+    #[default]
+    None,
+}
+
+impl Numbering {
+    /// The numbering of the 6 title levels when none is chosen
+    pub const DEFAULT: [Numbering; 6] = [
+        Numbering::Roman,
+        Numbering::Alpha,
+        Numbering::None,
+        Numbering::None,
+        Numbering::None,
+        Numbering::None,
+    ];
+
+    /// Parses the numbering of each title level, like `roman,alpha,dotted`.
+    /// The numberings can be separated by commas or spaces.
+    pub fn parse_levels(levels: &str) -> Result<Vec<Numbering>, String> {
+        let numbering = levels
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|level| !level.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if numbering.len() > 6 {
+            return Err("there are only 6 title levels to number".to_owned());
+        }
+
+        Ok(numbering)
+    }
 
+    /// Formats the number of a title, from the counts of titles of its level and its parent levels
+    pub(crate) fn format(self, counts: &[usize]) -> String {
+        let count = counts[counts.len() - 1];
+
+        match self {
+            Numbering::Roman => get_roman_numeral(count),
+            Numbering::Arabic => count.to_string(),
+            Numbering::Alpha => get_alpha_numeral(count),
+            Numbering::Dotted => counts
+                .iter()
+                .map(|count| count.to_string())
+                .collect::<Vec<_>>()
+                .join("."),
+            Numbering::None => String::new(),
+        }
+    }
+}
+
+impl FromStr for Numbering {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "roman" => Ok(Numbering::Roman),
+            "arabic" => Ok(Numbering::Arabic),
+            "alpha" => Ok(Numbering::Alpha),
+            "dotted" => Ok(Numbering::Dotted),
+            "none" => Ok(Numbering::None),
+            _ => Err(format!(
+                "unknown numbering {}, expected roman, arabic, alpha, dotted or none",
+                s
+            )),
+        }
+    }
+}
+
+/// The options used to render a Knots document
+#[derive(Debug, Clone)]
 pub struct KnotsOptions {
+    /// whether to create a summary of the titles
     pub summary: bool,
+    /// whether to show the completion of the tasks of each section in the summary
+    pub task_progress: bool,
+    /// the admonition kinds to register, possibly replacing built-in ones
+    pub admonitions: Vec<AdmonitionKind>,
+    /// the numbering of the first title levels, replacing the one chosen by the document
+    pub numbering: Option<Vec<Numbering>>,
+    /// where the relative paths of the document start from, instead of the directory of the document
+    pub asset_root: Option<PathBuf>,
+    /// how the local images are optimised, if they are
+    pub image_optimisation: Option<ImageOptimisation>,
+    /// whether to download the remote images to embed them, so the document works offline
+    pub embed_remote: bool,
+    /// where the optimised and downloaded images are kept between renders
+    pub cache_dir: Option<PathBuf>,
 }
 
-/// Transpiles to an HTML page our Knots objects
-pub fn transpile(parse_result: ParseResult, options: KnotsOptions) -> String {
+impl Default for KnotsOptions {
+    fn default() -> Self {
+        KnotsOptions {
+            summary: true,
+            task_progress: false,
+            admonitions: Vec::new(),
+            numbering: None,
+            asset_root: None,
+            image_optimisation: None,
+            embed_remote: false,
+            cache_dir: None,
+        }
+    }
+}
+
+/// Gathers the footnote definitions of a document
+#[derive(Default)]
+struct FootnoteCollector {
+    definitions: HashMap<String, FootnoteDefinition>,
+}
+
+impl Visitor for FootnoteCollector {
+    fn visit_footnote_definition(&mut self, definition: &FootnoteDefinition) {
+        self.definitions
+            .insert(definition.id.clone(), definition.clone());
+    }
+}
+
+/// Counts the references to the footnotes made inside other footnotes,
+/// in the order the footnotes are written
+struct FootnoteCounter {
+    /// the ids of the footnotes in order of first reference, with their number of references
+    footnotes: Vec<(String, usize)>,
+}
+
+impl Visitor for FootnoteCounter {
+    fn visit_footnote_reference(&mut self, reference: &FootnoteReference) {
+        count_footnote_reference(&mut self.footnotes, &reference.id);
+    }
+}
+
+/// Gathers the explicit ids of the document,
+/// so that the generated anchors don't take them
+#[derive(Default)]
+struct IdCollector {
+    ids: HashSet<String>,
+}
+
+impl Visitor for IdCollector {
+    fn visit_title(&mut self, title: &Title) {
+        self.ids.extend(title.id.clone());
+    }
+
+    fn visit_table(&mut self, table: &Table) {
+        self.ids.extend(table.id.clone());
+    }
+
+    fn visit_image(&mut self, image: &Image) {
+        self.ids.extend(image.id.clone());
+    }
+
+    fn visit_maths_block(&mut self, maths: &MathsBlock) {
+        self.ids.extend(maths.id.clone());
+    }
+}
+
+/// A Knots document rendered to HTML
+#[derive(Debug, Clone)]
+pub struct RenderResult {
+    /// the self-contained HTML page
+    pub html: String,
+    /// the problems which didn't prevent the document from being rendered, like missing images
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Renders a parsed Knots document to a self-contained HTML page
+pub fn render(parse_result: ParseResult, options: &KnotsOptions) -> RenderResult {
     let mut builder = Builder::new();
+    builder.admonition_kinds = options.admonitions.clone();
+    builder.image_optimisation = options.image_optimisation.clone();
+    builder.embed_remote = options.embed_remote;
+    builder.cache_dir = options.cache_dir.clone();
+
+    // the root of the options takes precedence over the directory of the document
+    if let Some(root) = options
+        .asset_root
+        .as_ref()
+        .or(parse_result.directory.as_ref())
+    {
+        builder.asset_root = root.clone();
+    }
+
+    let mut ids = IdCollector::default();
+    ids.visit_root(&parse_result.root_object);
+    builder.anchors = ids.ids;
+
+    // the numbering of the options takes precedence over the one of the document
+    let numbering = options
+        .numbering
+        .as_ref()
+        .or(parse_result.document_numbering.as_ref());
+
+    if let Some(numbering) = numbering {
+        for (level, &level_numbering) in builder.numbering.iter_mut().zip(numbering) {
+            *level = level_numbering;
+        }
+    }
 
     builder.orphan_tag("!DOCTYPE html", &[]);
     builder.start_tag("html", &[]);
@@ -66,11 +308,47 @@ pub fn transpile(parse_result: ParseResult, options: KnotsOptions) -> String {
     builder.start_tag("div", &[("class", "flex-container")]);
     builder.start_tag("div", &[("class", "main-content")]);
     builder.start_tag("div", &[("class", "container-lvl1")]);
-    builder.write_knots_object(parse_result.root_object);
+    builder.write_knots_object(&parse_result.root_object);
     builder.end_tag(); // </div> .lvl1-container
 
+    // footnotes, in order of first reference
+    if !builder.get_footnotes().is_empty() {
+        let mut footnotes = FootnoteCollector::default();
+        footnotes.visit_root(&parse_result.root_object);
+
+        builder.start_tag(
+            "div",
+            &[("class", "container footnotes"), ("id", "footnotes")],
+        );
+        builder.orphan_tag("hr", &[]);
+        builder.start_tag("ol", &[]);
+
+        // footnotes can reference other footnotes, even the ones written before them,
+        // so all the references are counted before writing the links back to them
+        let mut counter = FootnoteCounter {
+            footnotes: builder.get_footnotes().to_vec(),
+        };
+
+        let mut i = 0;
+        while let Some((id, _)) = counter.footnotes.get(i).cloned() {
+            if let Some(definition) = footnotes.definitions.get(&id) {
+                counter.visit_nodes(&definition.contents);
+            }
+            i += 1;
+        }
+
+        for (i, (id, references)) in counter.footnotes.iter().enumerate() {
+            if let Some(definition) = footnotes.definitions.get(id) {
+                definition.write_footnote(&mut builder, i + 1, *references);
+            }
+        }
+
+        builder.end_tag(); // </ol>
+        builder.end_tag(); // </div>
+    }
+
     // document license
-    if let Some(license) = parse_result.document_license {
+    if let Some(license) = &parse_result.document_license {
         builder.start_tag("div", &[("class", "docinfo discreet"), ("id", "license")]);
         builder.orphan_tag("hr", &[]);
         builder.write_content(include_str!("../icons/ereader.svg"));
@@ -92,13 +370,22 @@ pub fn transpile(parse_result: ParseResult, options: KnotsOptions) -> String {
 
         for item in summary {
             let item_class = format!("lvl{}", item.level);
+
+            let mut item_name = item.name;
+            if options.task_progress && item.tasks_total != 0 {
+                item_name += &format!(
+                    " <span class=\"task-progress\">{}/{}</span>",
+                    item.tasks_done, item.tasks_total
+                );
+            }
+
             builder.inline_tag(
                 "a",
                 &[
                     ("href", &format!("#{}", item.anchor)),
                     ("class", &item_class),
                 ],
-                &item.name,
+                &item_name,
             );
         }
 
@@ -109,6 +396,31 @@ pub fn transpile(parse_result: ParseResult, options: KnotsOptions) -> String {
 
     builder.end_tag(); // </div> .flex-content
 
+    // the colours of the registered admonitions override the built-in ones.
+    // They're checked first so they can't close the style tag
+    let coloured_kinds = options
+        .admonitions
+        .iter()
+        .filter(|kind| {
+            AdmonitionKind::is_valid_colour(&kind.colour)
+                && kind
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        .collect::<Vec<_>>();
+
+    if !coloured_kinds.is_empty() {
+        builder.start_tag("style", &[]);
+        for kind in coloured_kinds {
+            builder.write_content(&format!(
+                ".admonition-{} {{ background: {}; }}",
+                kind.name, kind.colour
+            ));
+        }
+        builder.end_tag(); // </style>
+    }
+
     if builder.should_include_katex {
         builder.start_tag("style", &[]);
         builder.write_content(include_str!("../css/katex.css"));
@@ -155,5 +467,228 @@ pub fn transpile(parse_result: ParseResult, options: KnotsOptions) -> String {
     builder.end_tag(); // </body>
     builder.end_tag(); // </html>
 
-    builder.into_result()
+    let diagnostics = builder
+        .get_warnings()
+        .iter()
+        .map(|(span, construct, explanation)| {
+            Diagnostic::warning(
+                &parse_result.name,
+                &parse_result.source,
+                span.start,
+                *construct,
+                explanation.clone(),
+            )
+        })
+        .collect();
+
+    RenderResult {
+        html: builder.into_result(),
+        diagnostics,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::Construct;
+    use crate::parser::parse_str;
+
+    #[test]
+    fn shows_the_task_progress_in_the_summary() {
+        let input = "# Chores\n\n- [x] Shop\n\n## Kitchen\n\n- [ ] Dishes\n\n# Notes\n";
+        let options = KnotsOptions {
+            task_progress: true,
+            ..KnotsOptions::default()
+        };
+        let html = render(parse_str(input, "chores.md").unwrap(), &options).html;
+
+        assert!(html.contains("I - Chores <span class=\"task-progress\">1/2</span>"));
+        assert!(html.contains("Kitchen <span class=\"task-progress\">0/1</span>"));
+        assert!(!html.contains("Notes <span"));
+    }
+
+    #[test]
+    fn parses_numbering_levels() {
+        assert_eq!(
+            Numbering::parse_levels("arabic, dotted none").unwrap(),
+            [Numbering::Arabic, Numbering::Dotted, Numbering::None]
+        );
+        assert!(Numbering::parse_levels("roman,roman,roman,roman,roman,roman,roman").is_err());
+    }
+
+    #[test]
+    fn numbers_titles_of_every_level() {
+        let input = "%numbering arabic,dotted,dotted\n\n# A\n\n## B\n\n### C\n\n###### F\n";
+        let html = render(
+            parse_str(input, "levels.md").unwrap(),
+            &KnotsOptions::default(),
+        )
+        .html;
+
+        assert!(html.contains(">1 - A</a>"));
+        assert!(html.contains(">1.1 - B</a>"));
+        assert!(html.contains(">1.1.1 - C</a>"));
+        assert!(html.contains("<h6 class=\"lvl6\""));
+
+        // the numbering of the options replaces the one of the document
+        let options = KnotsOptions {
+            numbering: Some(vec![Numbering::Arabic]),
+            ..KnotsOptions::default()
+        };
+        let html = render(parse_str(input, "levels.md").unwrap(), &options).html;
+        assert!(html.contains(">1 - A</a>"));
+        assert!(html.contains(">A - B</a>"));
+        assert!(html.contains("class=\"lvl3\">C</a>"));
+    }
+
+    #[test]
+    fn links_cross_references_to_their_target() {
+        let input = "See @ref(numbers) and @ref(intro).\n\n# Intro {#intro}\n\n| a |\n|---|\n| 1 |\nTable: Numbers {#numbers}\n";
+        let html = render(
+            parse_str(input, "refs.md").unwrap(),
+            &KnotsOptions::default(),
+        )
+        .html;
+
+        // the references are resolved even before their target
+        assert!(html.contains("<a href=\"#numbers\" class=\"ref\">Table 1</a>"));
+        assert!(html.contains("<a href=\"#intro\" class=\"ref\">I - Intro</a>"));
+        assert!(html.contains("<table id=\"numbers\">"));
+    }
+
+    #[test]
+    fn resolves_images_next_to_the_document() {
+        let dir = std::env::temp_dir().join(format!("knots-paths-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("img")).unwrap();
+        std::fs::write(dir.join("img/cat.svg"), "<svg></svg>").unwrap();
+        let document = dir.join("notes.md");
+        std::fs::write(&document, "![A cat](img/cat.svg)\n").unwrap();
+
+        let parse_result = crate::parser::parse_file(document.to_str().unwrap()).unwrap();
+        let rendered = render(parse_result.clone(), &KnotsOptions::default());
+        assert!(rendered.diagnostics.is_empty());
+        assert!(rendered
+            .html
+            .contains("<div aria-label=\"A cat\" class=\"svg-image\" role=\"img\">"));
+
+        // the asset root takes precedence over the directory of the document
+        let options = KnotsOptions {
+            asset_root: Some(dir.join("img")),
+            ..KnotsOptions::default()
+        };
+        let rendered = render(parse_result, &options);
+        assert_eq!(rendered.diagnostics.len(), 1);
+        assert!(rendered.diagnostics[0]
+            .explanation
+            .contains(&format!("{}", dir.join("img/img/cat.svg").display())));
+    }
+
+    #[test]
+    fn aligns_table_columns() {
+        let input = "Name | Price | Note\n:--|--:|---\nTea | 3 | hot\n";
+        let html = render(
+            parse_str(input, "prices.md").unwrap(),
+            &KnotsOptions::default(),
+        )
+        .html;
+
+        assert_eq!(html.matches("<td style=\"text-align: left\">").count(), 1);
+        assert_eq!(html.matches("<th style=\"text-align: right\">").count(), 1);
+        assert_eq!(html.matches("<td>").count(), 1);
+    }
+
+    #[test]
+    fn writes_code_block_attributes() {
+        let input = "```rust title=\"<main>.rs\" start=10 {11}\nfn main() {\n}\n```\n";
+        let html = render(
+            parse_str(input, "code.md").unwrap(),
+            &KnotsOptions::default(),
+        )
+        .html;
+
+        assert!(html.contains("<figcaption class=\"code-title\">&lt;main&gt;.rs</figcaption>"));
+        assert!(html.contains(
+            "<pre class=\"codeblock numbered\" data-line=\"11\" data-line-offset=\"9\">"
+        ));
+        assert!(html.contains("10\n<span class=\"highlighted\">11</span></span>"));
+    }
+
+    #[test]
+    fn checks_admonition_colours() {
+        assert!(AdmonitionKind::is_valid_colour("#80e27e"));
+        assert!(AdmonitionKind::is_valid_colour("#fff"));
+        assert!(AdmonitionKind::is_valid_colour("teal"));
+        assert!(!AdmonitionKind::is_valid_colour("#80e27"));
+        assert!(!AdmonitionKind::is_valid_colour(""));
+        assert!(!AdmonitionKind::is_valid_colour(
+            "red;}</style><script>alert(1)</script>"
+        ));
+    }
+
+    #[test]
+    fn ignores_unsafe_admonition_colours() {
+        let options = KnotsOptions {
+            admonitions: vec![
+                AdmonitionKind {
+                    name: "tip".to_owned(),
+                    icon: String::new(),
+                    colour: "red;}</style><script>alert(1)</script>".to_owned(),
+                },
+                AdmonitionKind {
+                    name: "idea".to_owned(),
+                    icon: String::new(),
+                    colour: "#80e27e".to_owned(),
+                },
+            ],
+            ..KnotsOptions::default()
+        };
+
+        let html = render(
+            parse_str(":::tip\nHello\n:::\n", "tip.md").unwrap(),
+            &options,
+        )
+        .html;
+
+        assert!(!html.contains("alert(1)"));
+        assert!(html.contains(".admonition-idea { background: #80e27e; }"));
+    }
+
+    #[test]
+    fn links_back_to_references_inside_footnotes() {
+        let input =
+            "Text[^a] and[^b].\n\n[^a]: First[^c].\n[^b]: Second, see[^a] and[^c].\n[^c]: Third.\n";
+        let html = render(
+            parse_str(input, "notes.md").unwrap(),
+            &KnotsOptions::default(),
+        )
+        .html;
+
+        // every reference has an anchor, and a link back to it from its footnote
+        for anchor in &["fnref-1", "fnref-1-2", "fnref-2", "fnref-3", "fnref-3-2"] {
+            assert!(html.contains(&format!("id=\"{}\"", anchor)), "{}", anchor);
+            assert!(
+                html.contains(&format!("href=\"#{}\"", anchor)),
+                "{}",
+                anchor
+            );
+        }
+
+        // and the footnotes are numbered in order of first reference
+        let first = html.find("id=\"fn-1\"").unwrap();
+        assert!(html[first..].find("First").unwrap() < html[first..].find("Second").unwrap());
+    }
+
+    #[test]
+    fn returns_the_rendering_diagnostics() {
+        let input = "# Photos\n\n![A photo](missing-photo.png)\n";
+        let rendered = render(
+            parse_str(input, "photos.md").unwrap(),
+            &KnotsOptions::default(),
+        );
+
+        assert_eq!(rendered.diagnostics.len(), 1);
+        assert_eq!(rendered.diagnostics[0].construct, Construct::Image);
+        assert_eq!(rendered.diagnostics[0].line, 3);
+        assert!(rendered.html.contains("Missing image: missing-photo.png"));
+    }
 }
@@ -1,43 +1,50 @@
-const ALPHABET: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
 
-/// Gets the lv1 title number as a roman thingy
-// CAVEAT: ugly and doesn't work after 39
-pub fn get_roman_numeral(num: usize) -> String {
-    let mut buf = String::new();
+/// The roman numerals, from the largest to the smallest.
+/// The subtractive forms like IV are included so they're picked before IIII.
+const ROMAN_NUMERALS: &[(usize, &str)] = &[
+    (1000, "M"),
+    (900, "CM"),
+    (500, "D"),
+    (400, "CD"),
+    (100, "C"),
+    (90, "XC"),
+    (50, "L"),
+    (40, "XL"),
+    (10, "X"),
+    (9, "IX"),
+    (5, "V"),
+    (4, "IV"),
+    (1, "I"),
+];
 
-    let unit = match num % 10 {
-        0 => "",
-        1 => "I",
-        2 => "II",
-        3 => "III",
-        4 => "IV",
-        5 => "V",
-        6 => "VI",
-        7 => "VII",
-        8 => "VIII",
-        9 => "IX",
-        _ => unreachable!(),
-    };
+/// Gets a title number as a roman numeral.
+/// Past 3999, more M are added.
+pub fn get_roman_numeral(mut num: usize) -> String {
+    let mut buf = String::new();
 
-    let dozen_num = num / 10;
-    for _ in 0..dozen_num {
-        buf.push('X');
+    for &(value, numeral) in ROMAN_NUMERALS {
+        while num >= value {
+            buf += numeral;
+            num -= value;
+        }
     }
 
-    buf += unit;
     buf
 }
 
-/// Gets a lv2 title number as a letter
-/// CAVEAT: only 26 possibilities
+/// Gets a title number as letters, continuing after Z with AA, AB...
 pub fn get_alpha_numeral(mut num: usize) -> String {
-    num -= 1;
+    let mut letters = Vec::new();
 
-    if num >= 25 {
-        num = 25
+    // bijective base 26: there's no zero digit, so A is 1 and Z is 26
+    while num > 0 {
+        num -= 1;
+        letters.push(ALPHABET[num % 26]);
+        num /= 26;
     }
 
-    ALPHABET[num..num + 1].to_owned()
+    letters.iter().rev().map(|&letter| letter as char).collect()
 }
 
 /// Escapes an HTML string
@@ -49,3 +56,123 @@ pub fn escape_html(input: &str) -> String {
     res = res.replace(">", "&gt;");
     res
 }
+
+/// Finds out the MIME type of an image from its first bytes
+pub fn sniff_image_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("image/jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if is_avif(data) {
+        Some("image/avif")
+    } else if is_svg(data) {
+        Some("image/svg+xml")
+    } else {
+        None
+    }
+}
+
+/// Is this an AVIF image ?
+/// They start with an `ftyp` box listing the brands the file is compatible with.
+fn is_avif(data: &[u8]) -> bool {
+    if data.len() < 16 || &data[4..8] != b"ftyp" {
+        return false;
+    }
+
+    let box_size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    let box_end = box_size.min(data.len());
+
+    // the major brand, then the compatible brands after the minor version
+    let compatible = data.get(16..box_end).unwrap_or_default();
+    std::iter::once(&data[8..12])
+        .chain(compatible.chunks_exact(4))
+        .any(|brand| brand == b"avif" || brand == b"avis")
+}
+
+/// Is this an SVG image ?
+fn is_svg(data: &[u8]) -> bool {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text.trim_start_matches('\u{feff}').trim_start(),
+        Err(_) => return false,
+    };
+
+    // the svg tag may come after an XML declaration, a doctype or comments
+    text.starts_with('<') && text.contains("<svg")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_roman_numerals() {
+        let numerals: Vec<_> = [1, 4, 9, 14, 40, 90, 400, 1994, 3999]
+            .iter()
+            .map(|&num| get_roman_numeral(num))
+            .collect();
+
+        assert_eq!(
+            numerals,
+            [
+                "I",
+                "IV",
+                "IX",
+                "XIV",
+                "XL",
+                "XC",
+                "CD",
+                "MCMXCIV",
+                "MMMCMXCIX"
+            ]
+        );
+        assert_eq!(get_roman_numeral(4001), "MMMMI");
+    }
+
+    #[test]
+    fn writes_alpha_numerals() {
+        let numerals: Vec<_> = [1, 26, 27, 52, 53, 702, 703]
+            .iter()
+            .map(|&num| get_alpha_numeral(num))
+            .collect();
+
+        assert_eq!(numerals, ["A", "Z", "AA", "AZ", "BA", "ZZ", "AAA"]);
+    }
+
+    #[test]
+    fn sniffs_image_types() {
+        let mut avif = b"\0\0\0\x1cftypmif1\0\0\0\0mif1avifmiaf".to_vec();
+        avif.extend_from_slice(&[0; 8]);
+
+        let types: Vec<_> = [
+            &b"\x89PNG\r\n\x1a\n\0\0"[..],
+            &[0xff, 0xd8, 0xff, 0xe0],
+            b"GIF89a",
+            b"RIFF\0\0\0\0WEBPVP8 ",
+            &avif,
+            "\u{feff}<?xml version=\"1.0\"?>\n<svg></svg>".as_bytes(),
+            b"<html><body></body></html>",
+            b"%PDF-1.4",
+        ]
+        .iter()
+        .map(|data| sniff_image_type(data))
+        .collect();
+
+        assert_eq!(
+            types,
+            [
+                Some("image/png"),
+                Some("image/jpeg"),
+                Some("image/gif"),
+                Some("image/webp"),
+                Some("image/avif"),
+                Some("image/svg+xml"),
+                None,
+                None
+            ]
+        );
+    }
+}
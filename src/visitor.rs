@@ -0,0 +1,364 @@
+//! Traversal of the Knots document tree.
+//!
+//! Implement [`Visitor`] to inspect a document, or [`VisitorMut`] to transform it.
+//! Every method walks the children of its node by default,
+//! so you only need to override the ones you care about:
+//!
+//! ```
+//! use knots::knots_objects::Link;
+//! use knots::visitor::Visitor;
+//!
+//! #[derive(Default)]
+//! struct LinkCollector {
+//!     links: Vec<String>,
+//! }
+//!
+//! impl Visitor for LinkCollector {
+//!     fn visit_link(&mut self, link: &Link) {
+//!         self.links.push(link.link.clone());
+//!     }
+//! }
+//!
+//! let parse_result = knots::parse_str("See [Knots](https://github.com)", "links.md").unwrap();
+//! let mut collector = LinkCollector::default();
+//! collector.visit_root(&parse_result.root_object);
+//! assert_eq!(collector.links, ["https://github.com"]);
+//! ```
+
+use crate::knots_objects::*;
+
+/// Walks the document tree
+pub trait Visitor {
+    fn visit_root(&mut self, root: &Root) {
+        self.visit_nodes(&root.contents);
+    }
+
+    fn visit_nodes(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            self.visit_node(node);
+        }
+    }
+
+    fn visit_node(&mut self, node: &Node) {
+        match node {
+            Node::Title(title) => self.visit_title(title),
+            Node::Paragraph(paragraph) => self.visit_paragraph(paragraph),
+            Node::BlockQuote(quote) => self.visit_block_quote(quote),
+            Node::Admonition(admonition) => self.visit_admonition(admonition),
+            Node::List(list) => self.visit_list(list),
+            Node::Table(table) => self.visit_table(table),
+            Node::BasicText(text) => self.visit_basic_text(text),
+            Node::Italic(italic) => self.visit_italic(italic),
+            Node::Bold(bold) => self.visit_bold(bold),
+            Node::Strikethrough(strikethrough) => self.visit_strikethrough(strikethrough),
+            Node::Highlight(highlight) => self.visit_highlight(highlight),
+            Node::Subscript(subscript) => self.visit_subscript(subscript),
+            Node::Superscript(superscript) => self.visit_superscript(superscript),
+            Node::Keyboard(keyboard) => self.visit_keyboard(keyboard),
+            Node::Link(link) => self.visit_link(link),
+            Node::LinkDefinition(definition) => self.visit_link_definition(definition),
+            Node::FootnoteReference(reference) => self.visit_footnote_reference(reference),
+            Node::FootnoteDefinition(definition) => self.visit_footnote_definition(definition),
+            Node::CrossReference(reference) => self.visit_cross_reference(reference),
+            Node::Image(image) => self.visit_image(image),
+            Node::HorizontalRule(hr) => self.visit_horizontal_rule(hr),
+            Node::InlineCode(code) => self.visit_inline_code(code),
+            Node::InlineMaths(maths) => self.visit_inline_maths(maths),
+            Node::CodeBlock(code) => self.visit_code_block(code),
+            Node::MathsBlock(maths) => self.visit_maths_block(maths),
+            Node::Mermaid(mermaid) => self.visit_mermaid(mermaid),
+        }
+    }
+
+    fn visit_title(&mut self, _title: &Title) {}
+
+    fn visit_paragraph(&mut self, paragraph: &Paragraph) {
+        self.visit_nodes(&paragraph.contents);
+    }
+
+    fn visit_block_quote(&mut self, quote: &BlockQuote) {
+        self.visit_nodes(&quote.contents);
+    }
+
+    fn visit_admonition(&mut self, admonition: &Admonition) {
+        if let Some(title) = &admonition.title {
+            self.visit_nodes(title);
+        }
+
+        self.visit_nodes(&admonition.contents);
+    }
+
+    fn visit_list(&mut self, list: &List) {
+        for item in &list.contents {
+            self.visit_nodes(&item.contents);
+        }
+    }
+
+    fn visit_table(&mut self, table: &Table) {
+        if let Some(caption) = &table.caption {
+            self.visit_nodes(caption);
+        }
+
+        for cell in &table.header {
+            self.visit_nodes(cell);
+        }
+
+        for row in &table.rows {
+            for cell in row {
+                self.visit_nodes(cell);
+            }
+        }
+    }
+
+    fn visit_basic_text(&mut self, _text: &BasicText) {}
+
+    fn visit_italic(&mut self, italic: &Italic) {
+        self.visit_nodes(&italic.contents);
+    }
+
+    fn visit_bold(&mut self, bold: &Bold) {
+        self.visit_nodes(&bold.contents);
+    }
+
+    fn visit_strikethrough(&mut self, strikethrough: &Strikethrough) {
+        self.visit_nodes(&strikethrough.contents);
+    }
+
+    fn visit_highlight(&mut self, highlight: &Highlight) {
+        self.visit_nodes(&highlight.contents);
+    }
+
+    fn visit_subscript(&mut self, _subscript: &Subscript) {}
+
+    fn visit_superscript(&mut self, _superscript: &Superscript) {}
+
+    fn visit_keyboard(&mut self, _keyboard: &Keyboard) {}
+
+    fn visit_link(&mut self, link: &Link) {
+        self.visit_nodes(&link.contents);
+    }
+
+    fn visit_link_definition(&mut self, _definition: &LinkDefinition) {}
+
+    fn visit_footnote_reference(&mut self, _reference: &FootnoteReference) {}
+
+    fn visit_footnote_definition(&mut self, definition: &FootnoteDefinition) {
+        self.visit_nodes(&definition.contents);
+    }
+
+    fn visit_cross_reference(&mut self, _reference: &CrossReference) {}
+
+    fn visit_image(&mut self, image: &Image) {
+        if let Some(caption) = &image.caption {
+            self.visit_nodes(caption);
+        }
+    }
+
+    fn visit_horizontal_rule(&mut self, _hr: &HorizontalRule) {}
+
+    fn visit_inline_code(&mut self, _code: &InlineCode) {}
+
+    fn visit_inline_maths(&mut self, _maths: &InlineMaths) {}
+
+    fn visit_code_block(&mut self, _code: &CodeBlock) {}
+
+    fn visit_maths_block(&mut self, _maths: &MathsBlock) {}
+
+    fn visit_mermaid(&mut self, _mermaid: &Mermaid) {}
+}
+
+/// Walks the document tree, allowing to modify it
+pub trait VisitorMut {
+    fn visit_root_mut(&mut self, root: &mut Root) {
+        self.visit_nodes_mut(&mut root.contents);
+    }
+
+    fn visit_nodes_mut(&mut self, nodes: &mut Vec<Node>) {
+        for node in nodes {
+            self.visit_node_mut(node);
+        }
+    }
+
+    fn visit_node_mut(&mut self, node: &mut Node) {
+        match node {
+            Node::Title(title) => self.visit_title_mut(title),
+            Node::Paragraph(paragraph) => self.visit_paragraph_mut(paragraph),
+            Node::BlockQuote(quote) => self.visit_block_quote_mut(quote),
+            Node::Admonition(admonition) => self.visit_admonition_mut(admonition),
+            Node::List(list) => self.visit_list_mut(list),
+            Node::Table(table) => self.visit_table_mut(table),
+            Node::BasicText(text) => self.visit_basic_text_mut(text),
+            Node::Italic(italic) => self.visit_italic_mut(italic),
+            Node::Bold(bold) => self.visit_bold_mut(bold),
+            Node::Strikethrough(strikethrough) => self.visit_strikethrough_mut(strikethrough),
+            Node::Highlight(highlight) => self.visit_highlight_mut(highlight),
+            Node::Subscript(subscript) => self.visit_subscript_mut(subscript),
+            Node::Superscript(superscript) => self.visit_superscript_mut(superscript),
+            Node::Keyboard(keyboard) => self.visit_keyboard_mut(keyboard),
+            Node::Link(link) => self.visit_link_mut(link),
+            Node::LinkDefinition(definition) => self.visit_link_definition_mut(definition),
+            Node::FootnoteReference(reference) => self.visit_footnote_reference_mut(reference),
+            Node::FootnoteDefinition(definition) => self.visit_footnote_definition_mut(definition),
+            Node::CrossReference(reference) => self.visit_cross_reference_mut(reference),
+            Node::Image(image) => self.visit_image_mut(image),
+            Node::HorizontalRule(hr) => self.visit_horizontal_rule_mut(hr),
+            Node::InlineCode(code) => self.visit_inline_code_mut(code),
+            Node::InlineMaths(maths) => self.visit_inline_maths_mut(maths),
+            Node::CodeBlock(code) => self.visit_code_block_mut(code),
+            Node::MathsBlock(maths) => self.visit_maths_block_mut(maths),
+            Node::Mermaid(mermaid) => self.visit_mermaid_mut(mermaid),
+        }
+    }
+
+    fn visit_title_mut(&mut self, _title: &mut Title) {}
+
+    fn visit_paragraph_mut(&mut self, paragraph: &mut Paragraph) {
+        self.visit_nodes_mut(&mut paragraph.contents);
+    }
+
+    fn visit_block_quote_mut(&mut self, quote: &mut BlockQuote) {
+        self.visit_nodes_mut(&mut quote.contents);
+    }
+
+    fn visit_admonition_mut(&mut self, admonition: &mut Admonition) {
+        if let Some(title) = &mut admonition.title {
+            self.visit_nodes_mut(title);
+        }
+
+        self.visit_nodes_mut(&mut admonition.contents);
+    }
+
+    fn visit_list_mut(&mut self, list: &mut List) {
+        for item in &mut list.contents {
+            self.visit_nodes_mut(&mut item.contents);
+        }
+    }
+
+    fn visit_table_mut(&mut self, table: &mut Table) {
+        if let Some(caption) = &mut table.caption {
+            self.visit_nodes_mut(caption);
+        }
+
+        for cell in &mut table.header {
+            self.visit_nodes_mut(cell);
+        }
+
+        for row in &mut table.rows {
+            for cell in row {
+                self.visit_nodes_mut(cell);
+            }
+        }
+    }
+
+    fn visit_basic_text_mut(&mut self, _text: &mut BasicText) {}
+
+    fn visit_italic_mut(&mut self, italic: &mut Italic) {
+        self.visit_nodes_mut(&mut italic.contents);
+    }
+
+    fn visit_bold_mut(&mut self, bold: &mut Bold) {
+        self.visit_nodes_mut(&mut bold.contents);
+    }
+
+    fn visit_strikethrough_mut(&mut self, strikethrough: &mut Strikethrough) {
+        self.visit_nodes_mut(&mut strikethrough.contents);
+    }
+
+    fn visit_highlight_mut(&mut self, highlight: &mut Highlight) {
+        self.visit_nodes_mut(&mut highlight.contents);
+    }
+
+    fn visit_subscript_mut(&mut self, _subscript: &mut Subscript) {}
+
+    fn visit_superscript_mut(&mut self, _superscript: &mut Superscript) {}
+
+    fn visit_keyboard_mut(&mut self, _keyboard: &mut Keyboard) {}
+
+    fn visit_link_mut(&mut self, link: &mut Link) {
+        self.visit_nodes_mut(&mut link.contents);
+    }
+
+    fn visit_link_definition_mut(&mut self, _definition: &mut LinkDefinition) {}
+
+    fn visit_footnote_reference_mut(&mut self, _reference: &mut FootnoteReference) {}
+
+    fn visit_footnote_definition_mut(&mut self, definition: &mut FootnoteDefinition) {
+        self.visit_nodes_mut(&mut definition.contents);
+    }
+
+    fn visit_cross_reference_mut(&mut self, _reference: &mut CrossReference) {}
+
+    fn visit_image_mut(&mut self, image: &mut Image) {
+        if let Some(caption) = &mut image.caption {
+            self.visit_nodes_mut(caption);
+        }
+    }
+
+    fn visit_horizontal_rule_mut(&mut self, _hr: &mut HorizontalRule) {}
+
+    fn visit_inline_code_mut(&mut self, _code: &mut InlineCode) {}
+
+    fn visit_inline_maths_mut(&mut self, _maths: &mut InlineMaths) {}
+
+    fn visit_code_block_mut(&mut self, _code: &mut CodeBlock) {}
+
+    fn visit_maths_block_mut(&mut self, _maths: &mut MathsBlock) {}
+
+    fn visit_mermaid_mut(&mut self, _mermaid: &mut Mermaid) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_str;
+
+    const INPUT: &str =
+        "> A *quote*, [link](https://github.com)\n\n- An **item**\n\n| Head | Cell |\n|---|---|\n| *row* | end |\n";
+
+    #[derive(Default)]
+    struct TextCollector {
+        texts: Vec<String>,
+    }
+
+    impl Visitor for TextCollector {
+        fn visit_basic_text(&mut self, text: &BasicText) {
+            if !text.contents.trim().is_empty() {
+                self.texts.push(text.contents.trim().to_owned());
+            }
+        }
+    }
+
+    struct Shouter;
+
+    impl VisitorMut for Shouter {
+        fn visit_basic_text_mut(&mut self, text: &mut BasicText) {
+            text.contents = text.contents.to_uppercase();
+        }
+    }
+
+    #[test]
+    fn visits_nested_nodes() {
+        let parse_result = parse_str(INPUT, "texts.md").unwrap();
+        let mut collector = TextCollector::default();
+        collector.visit_root(&parse_result.root_object);
+
+        assert_eq!(
+            collector.texts,
+            ["A", "quote", ",", "link", "An", "item", "Head", "Cell", "row", "end"]
+        );
+    }
+
+    #[test]
+    fn modifies_nested_nodes() {
+        let mut parse_result = parse_str(INPUT, "texts.md").unwrap();
+        Shouter.visit_root_mut(&mut parse_result.root_object);
+
+        let mut collector = TextCollector::default();
+        collector.visit_root(&parse_result.root_object);
+
+        assert_eq!(
+            collector.texts,
+            ["A", "QUOTE", ",", "LINK", "AN", "ITEM", "HEAD", "CELL", "ROW", "END"]
+        );
+    }
+}